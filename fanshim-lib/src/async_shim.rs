@@ -0,0 +1,142 @@
+use crate::cpu::Hertz;
+use crate::hooks::Trigger;
+use crate::notify::NotifyState;
+use crate::status::Indication;
+use crate::throttle::Throttled;
+use crate::{Controller, MilliCelsius, ShimLayer};
+use std::future::{poll_fn, Future};
+use std::pin::pin;
+use std::task::Poll;
+use std::time::{Duration, SystemTime};
+
+/// A `ShimLayer` that can wait without blocking the thread.
+///
+/// Only the waits are async. The readings and the fan and LED commands of a tick still block
+/// the thread they run on: `RealShim` reads sysfs, drives the GPIO pins and, on kernels
+/// without the sysfs node for the throttle flags, runs `vcgencmd` once every
+/// `seconds_between_checks` of the `[throttle]` section.
+pub trait AsyncShimLayer: ShimLayer {
+    /// Waits `dur`, taking the place of `ShimLayer::sleep`.
+    fn wait(&mut self, dur: Duration) -> impl Future<Output = ()>;
+}
+
+/// Makes any shim an `AsyncShimLayer`, waiting with the timer of the runtime, e.g.
+/// `Timed::new(RealShim::new(), tokio::time::sleep)`.
+pub struct Timed<S, F> {
+    shim: S,
+    timer: F,
+}
+
+impl<S, F> Timed<S, F> {
+    pub fn new(shim: S, timer: F) -> Self {
+        Self { shim, timer }
+    }
+
+    pub fn shim(&mut self) -> &mut S {
+        &mut self.shim
+    }
+
+    pub fn into_inner(self) -> S {
+        self.shim
+    }
+}
+
+impl<S, F, W> AsyncShimLayer for Timed<S, F>
+where
+    S: ShimLayer,
+    F: FnMut(Duration) -> W,
+    W: Future<Output = ()>,
+{
+    fn wait(&mut self, dur: Duration) -> impl Future<Output = ()> {
+        (self.timer)(dur)
+    }
+}
+
+impl<S: ShimLayer, F> ShimLayer for Timed<S, F> {
+    fn get_cpu_temperature(&mut self) -> MilliCelsius {
+        self.shim.get_cpu_temperature()
+    }
+    fn get_cpu_load(&mut self) -> Option<f32> {
+        self.shim.get_cpu_load()
+    }
+    fn get_cpu_frequency(&mut self) -> Option<Hertz> {
+        self.shim.get_cpu_frequency()
+    }
+    fn get_throttled(&mut self) -> Option<Throttled> {
+        self.shim.get_throttled()
+    }
+    fn get_status(&mut self) -> Option<Indication> {
+        self.shim.get_status()
+    }
+
+    fn fan_is_enabled(&mut self) -> bool {
+        self.shim.fan_is_enabled()
+    }
+    fn turn_fan_on(&mut self) {
+        self.shim.turn_fan_on()
+    }
+    fn turn_fan_off(&mut self) {
+        self.shim.turn_fan_off()
+    }
+    fn set_fan_duty(&mut self, duty: f32) {
+        self.shim.set_fan_duty(duty)
+    }
+
+    fn set_led_rgb_brightness(&mut self, r: u8, g: u8, b: u8, brightness: f32) {
+        self.shim.set_led_rgb_brightness(r, g, b, brightness)
+    }
+
+    fn button_pressed(&mut self) -> bool {
+        self.shim.button_pressed()
+    }
+    fn run_hook(&mut self, trigger: Trigger) {
+        self.shim.run_hook(trigger)
+    }
+
+    fn sleep(&mut self, dur: Duration) {
+        self.shim.sleep(dur)
+    }
+    fn now(&self) -> SystemTime {
+        self.shim.now()
+    }
+
+    fn notify(&mut self, state: NotifyState) {
+        self.shim.notify(state)
+    }
+}
+
+impl Controller {
+    /// Controls the fan until `shutdown` completes, which also ends the wait for the next
+    /// reading. Kick-starting the fan waits with `AsyncShimLayer::wait` as well.
+    ///
+    /// Each `tick` runs on the task's thread and blocks it for as long as the shim does, which
+    /// is fine for shims that do not block and for runtimes that can spare the thread. To keep
+    /// `RealShim` off the runtime's threads, call `tick` from a blocking task instead, as the
+    /// README shows for tokio.
+    pub async fn run<T, F>(&mut self, s: &mut T, shutdown: F)
+    where
+        T: AsyncShimLayer,
+        F: Future<Output = ()>,
+    {
+        let mut shutdown = pin!(shutdown);
+        loop {
+            if let Some(kick) = self.tick(s) {
+                s.wait(kick).await;
+                self.end_kick_start(s);
+            }
+
+            let mut wait = pin!(s.wait(self.config().delay_between_readings));
+            let stopped = poll_fn(|cx| {
+                if shutdown.as_mut().poll(cx).is_ready() {
+                    Poll::Ready(true)
+                } else {
+                    wait.as_mut().poll(cx).map(|()| false)
+                }
+            })
+            .await;
+            if stopped {
+                return;
+            }
+        }
+    }
+}
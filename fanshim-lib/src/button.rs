@@ -1,5 +1,10 @@
 use rppal::gpio::Gpio;
+use rppal::gpio::InputPin;
 use rppal::gpio::Level::Low;
+use rppal::gpio::Trigger;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 const BUTTON_ENABLED_PIN: u8 = 17;
 
@@ -14,3 +19,130 @@ pub fn button_is_depressed() -> bool {
 
     pin.read() == Low
 }
+
+/// Remembers that the button was pressed until taken, so the thread polling the button can
+/// tell the driver loop.
+#[derive(Debug, Clone, Default)]
+pub struct PressLatch(Arc<AtomicBool>);
+
+impl PressLatch {
+    pub fn set(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether the button was pressed since the last call.
+    pub fn take(&self) -> bool {
+        self.0.swap(false, Ordering::SeqCst)
+    }
+}
+
+/// The button, kept open for watching it.
+pub struct Button {
+    pin: InputPin,
+    presses: PressLatch,
+}
+
+impl Button {
+    pub fn new() -> rppal::gpio::Result<Self> {
+        Self::with_latch(PressLatch::default())
+    }
+
+    /// The button, setting `presses` whenever it is seen pressed.
+    pub fn with_latch(presses: PressLatch) -> rppal::gpio::Result<Self> {
+        let mut pin = Gpio::new()?.get(BUTTON_ENABLED_PIN)?.into_input_pullup();
+        pin.set_reset_on_drop(false);
+        pin.set_interrupt(Trigger::Both)?;
+        Ok(Self { pin, presses })
+    }
+
+    pub fn is_pressed(&self) -> bool {
+        let pressed = self.pin.read() == Low;
+        if pressed {
+            self.presses.set();
+        }
+        pressed
+    }
+
+    /// Blocks until the button is pressed or released, or until `timeout` passes, and returns
+    /// whether it is pressed then.
+    pub fn wait_for_change(&mut self, timeout: Option<Duration>) -> rppal::gpio::Result<bool> {
+        // Presses shorter than it takes to read the pin again still count.
+        if self.pin.poll_interrupt(false, timeout)? == Some(Low) {
+            self.presses.set();
+        }
+        Ok(self.is_pressed())
+    }
+}
+
+/// Recognises the button being held down for a while.
+pub struct LongPress {
+    duration: Duration,
+    pressed_since: Option<Instant>,
+    fired: bool,
+}
+
+impl LongPress {
+    pub fn new(duration: Duration) -> Self {
+        Self {
+            duration,
+            pressed_since: None,
+            fired: false,
+        }
+    }
+
+    /// Takes the button state polled at `now`. True once per press, as soon as it has been
+    /// held for the configured duration.
+    pub fn update(&mut self, pressed: bool, now: Instant) -> bool {
+        if !pressed {
+            self.pressed_since = None;
+            self.fired = false;
+            return false;
+        }
+
+        let since = *self.pressed_since.get_or_insert(now);
+        if !self.fired && now.duration_since(since) >= self.duration {
+            self.fired = true;
+            return true;
+        }
+        false
+    }
+
+    /// How long the button still has to be held at `now` for the press to count, `None` while
+    /// it is released or once the press counted.
+    pub fn remaining(&self, now: Instant) -> Option<Duration> {
+        match self.pressed_since {
+            Some(since) if !self.fired => {
+                Some(self.duration.saturating_sub(now.duration_since(since)))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::LongPress;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn long_press_fires_once_per_hold() {
+        let mut l = LongPress::new(Duration::from_secs(2));
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        assert_eq!(l.remaining(at(0)), None);
+        assert!(!l.update(true, at(0)));
+        assert!(!l.update(true, at(1_900)));
+        assert_eq!(l.remaining(at(1_900)), Some(Duration::from_millis(100)));
+        assert!(l.update(true, at(2_000)));
+        assert_eq!(l.remaining(at(2_000)), None);
+        assert!(!l.update(true, at(5_000)));
+
+        // A short press in between does not count.
+        assert!(!l.update(false, at(5_100)));
+        assert!(!l.update(true, at(5_200)));
+        assert!(!l.update(false, at(5_300)));
+        assert!(!l.update(true, at(6_000)));
+        assert!(l.update(true, at(8_000)));
+    }
+}
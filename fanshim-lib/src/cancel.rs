@@ -0,0 +1,48 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Tells `main_loop` to return after the current reading.
+///
+/// Clones share the same state, so a clone can be handed to a signal handler or another
+/// thread while the original is passed to `main_loop`.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Makes the token usable for another `main_loop`, e.g. after reloading the config.
+    pub fn reset(&self) {
+        self.cancelled.store(false, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::CancellationToken;
+
+    #[test]
+    fn clones_share_cancellation() {
+        let token = CancellationToken::new();
+        let handle = token.clone();
+        assert!(!token.is_cancelled());
+
+        handle.cancel();
+        assert!(token.is_cancelled());
+
+        token.reset();
+        assert!(!handle.is_cancelled());
+    }
+}
@@ -0,0 +1,30 @@
+use std::time::{Duration, SystemTime};
+
+/// A clock that only moves when it is advanced, for simulations, replays and tests.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VirtualClock {
+    start: SystemTime,
+    elapsed: Duration,
+}
+
+impl VirtualClock {
+    pub fn new(start: SystemTime) -> Self {
+        Self {
+            start,
+            elapsed: Duration::from_secs(0),
+        }
+    }
+
+    pub fn now(&self) -> SystemTime {
+        self.start + self.elapsed
+    }
+
+    /// Time the clock was advanced by since it was created.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    pub fn advance(&mut self, dur: Duration) {
+        self.elapsed += dur;
+    }
+}
@@ -0,0 +1,357 @@
+use serde::de::{self, Deserializer, SeqAccess, Visitor};
+use serde::{Deserialize, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+/// An RGB colour.
+///
+/// In the config file, a colour is an `[r, g, b]` array, a hex string like `"#ff8800"` or
+/// `"#f80"`, a CSS colour name like `"orange"`, or `"hsv(30, 1.0, 1.0)"` with the hue in degrees.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Color {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+}
+
+impl Color {
+    pub const BLACK: Color = Color::rgb(0, 0, 0);
+
+    pub const fn rgb(red: u8, green: u8, blue: u8) -> Self {
+        Self { red, green, blue }
+    }
+
+    /// `hue` in degrees, `saturation` and `value` from 0.0 to 1.0.
+    pub fn hsv(hue: f32, saturation: f32, value: f32) -> Self {
+        let hue = hue.rem_euclid(360.0) / 60.0;
+        let saturation = saturation.clamp(0.0, 1.0);
+        let value = value.clamp(0.0, 1.0);
+
+        let chroma = value * saturation;
+        let x = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+        let (r, g, b) = match hue as u32 {
+            0 => (chroma, x, 0.0),
+            1 => (x, chroma, 0.0),
+            2 => (0.0, chroma, x),
+            3 => (0.0, x, chroma),
+            4 => (x, 0.0, chroma),
+            _ => (chroma, 0.0, x),
+        };
+        let m = value - chroma;
+        let channel = |c: f32| ((c + m) * 255.0).round() as u8;
+        Self::rgb(channel(r), channel(g), channel(b))
+    }
+}
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.red, self.green, self.blue)
+    }
+}
+
+impl From<(u8, u8, u8)> for Color {
+    fn from((red, green, blue): (u8, u8, u8)) -> Self {
+        Self::rgb(red, green, blue)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseColorError(String);
+
+impl fmt::Display for ParseColorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid colour `{}`, expected a hex colour like \"#ff8800\", a colour name or \"hsv(30, 1.0, 1.0)\"",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for ParseColorError {}
+
+impl FromStr for Color {
+    type Err = ParseColorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || ParseColorError(s.to_owned());
+        let trimmed = s.trim().to_ascii_lowercase();
+
+        if let Some(hex) = trimmed.strip_prefix('#') {
+            let value = u32::from_str_radix(hex, 16).map_err(|_| error())?;
+            return match hex.len() {
+                6 => Ok(Self::rgb(
+                    (value >> 16) as u8,
+                    (value >> 8) as u8,
+                    value as u8,
+                )),
+                // Every digit doubled, so "#f80" is "#ff8800".
+                3 => {
+                    let digit = |shift: u32| ((value >> shift) & 0xf) as u8 * 0x11;
+                    Ok(Self::rgb(digit(8), digit(4), digit(0)))
+                }
+                _ => Err(error()),
+            };
+        }
+
+        if let Some(args) = trimmed
+            .strip_prefix("hsv(")
+            .and_then(|rest| rest.strip_suffix(')'))
+        {
+            let values = args
+                .split(',')
+                .map(|v| v.trim().parse::<f32>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| error())?;
+            return match values[..] {
+                [h, s, v] if (0.0..=1.0).contains(&s) && (0.0..=1.0).contains(&v) => {
+                    Ok(Self::hsv(h, s, v))
+                }
+                _ => Err(error()),
+            };
+        }
+
+        let name: String = trimmed.chars().filter(|c| !c.is_whitespace()).collect();
+        NAMED_COLORS
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|&(_, rgb)| Self::rgb((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8))
+            .ok_or_else(error)
+    }
+}
+
+/// Written as hex, which is read back unchanged.
+impl Serialize for Color {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Color {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct ColorVisitor;
+
+        impl<'de> Visitor<'de> for ColorVisitor {
+            type Value = Color;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("an [r, g, b] array or a colour string")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Color, E> {
+                v.parse().map_err(E::custom)
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Color, A::Error> {
+                let mut channel = |i| {
+                    seq.next_element::<u8>()?
+                        .ok_or_else(|| de::Error::invalid_length(i, &self))
+                };
+                let color = Color::rgb(channel(0)?, channel(1)?, channel(2)?);
+                if seq.next_element::<de::IgnoredAny>()?.is_some() {
+                    return Err(de::Error::invalid_length(4, &self));
+                }
+                Ok(color)
+            }
+        }
+
+        deserializer.deserialize_any(ColorVisitor)
+    }
+}
+
+/// The CSS colour names, which cover the common X11 names.
+const NAMED_COLORS: [(&str, u32); 148] = [
+    ("aliceblue", 0xf0f8ff),
+    ("antiquewhite", 0xfaebd7),
+    ("aqua", 0x00ffff),
+    ("aquamarine", 0x7fffd4),
+    ("azure", 0xf0ffff),
+    ("beige", 0xf5f5dc),
+    ("bisque", 0xffe4c4),
+    ("black", 0x000000),
+    ("blanchedalmond", 0xffebcd),
+    ("blue", 0x0000ff),
+    ("blueviolet", 0x8a2be2),
+    ("brown", 0xa52a2a),
+    ("burlywood", 0xdeb887),
+    ("cadetblue", 0x5f9ea0),
+    ("chartreuse", 0x7fff00),
+    ("chocolate", 0xd2691e),
+    ("coral", 0xff7f50),
+    ("cornflowerblue", 0x6495ed),
+    ("cornsilk", 0xfff8dc),
+    ("crimson", 0xdc143c),
+    ("cyan", 0x00ffff),
+    ("darkblue", 0x00008b),
+    ("darkcyan", 0x008b8b),
+    ("darkgoldenrod", 0xb8860b),
+    ("darkgray", 0xa9a9a9),
+    ("darkgreen", 0x006400),
+    ("darkgrey", 0xa9a9a9),
+    ("darkkhaki", 0xbdb76b),
+    ("darkmagenta", 0x8b008b),
+    ("darkolivegreen", 0x556b2f),
+    ("darkorange", 0xff8c00),
+    ("darkorchid", 0x9932cc),
+    ("darkred", 0x8b0000),
+    ("darksalmon", 0xe9967a),
+    ("darkseagreen", 0x8fbc8f),
+    ("darkslateblue", 0x483d8b),
+    ("darkslategray", 0x2f4f4f),
+    ("darkslategrey", 0x2f4f4f),
+    ("darkturquoise", 0x00ced1),
+    ("darkviolet", 0x9400d3),
+    ("deeppink", 0xff1493),
+    ("deepskyblue", 0x00bfff),
+    ("dimgray", 0x696969),
+    ("dimgrey", 0x696969),
+    ("dodgerblue", 0x1e90ff),
+    ("firebrick", 0xb22222),
+    ("floralwhite", 0xfffaf0),
+    ("forestgreen", 0x228b22),
+    ("fuchsia", 0xff00ff),
+    ("gainsboro", 0xdcdcdc),
+    ("ghostwhite", 0xf8f8ff),
+    ("gold", 0xffd700),
+    ("goldenrod", 0xdaa520),
+    ("gray", 0x808080),
+    ("green", 0x008000),
+    ("greenyellow", 0xadff2f),
+    ("grey", 0x808080),
+    ("honeydew", 0xf0fff0),
+    ("hotpink", 0xff69b4),
+    ("indianred", 0xcd5c5c),
+    ("indigo", 0x4b0082),
+    ("ivory", 0xfffff0),
+    ("khaki", 0xf0e68c),
+    ("lavender", 0xe6e6fa),
+    ("lavenderblush", 0xfff0f5),
+    ("lawngreen", 0x7cfc00),
+    ("lemonchiffon", 0xfffacd),
+    ("lightblue", 0xadd8e6),
+    ("lightcoral", 0xf08080),
+    ("lightcyan", 0xe0ffff),
+    ("lightgoldenrodyellow", 0xfafad2),
+    ("lightgray", 0xd3d3d3),
+    ("lightgreen", 0x90ee90),
+    ("lightgrey", 0xd3d3d3),
+    ("lightpink", 0xffb6c1),
+    ("lightsalmon", 0xffa07a),
+    ("lightseagreen", 0x20b2aa),
+    ("lightskyblue", 0x87cefa),
+    ("lightslategray", 0x778899),
+    ("lightslategrey", 0x778899),
+    ("lightsteelblue", 0xb0c4de),
+    ("lightyellow", 0xffffe0),
+    ("lime", 0x00ff00),
+    ("limegreen", 0x32cd32),
+    ("linen", 0xfaf0e6),
+    ("magenta", 0xff00ff),
+    ("maroon", 0x800000),
+    ("mediumaquamarine", 0x66cdaa),
+    ("mediumblue", 0x0000cd),
+    ("mediumorchid", 0xba55d3),
+    ("mediumpurple", 0x9370db),
+    ("mediumseagreen", 0x3cb371),
+    ("mediumslateblue", 0x7b68ee),
+    ("mediumspringgreen", 0x00fa9a),
+    ("mediumturquoise", 0x48d1cc),
+    ("mediumvioletred", 0xc71585),
+    ("midnightblue", 0x191970),
+    ("mintcream", 0xf5fffa),
+    ("mistyrose", 0xffe4e1),
+    ("moccasin", 0xffe4b5),
+    ("navajowhite", 0xffdead),
+    ("navy", 0x000080),
+    ("oldlace", 0xfdf5e6),
+    ("olive", 0x808000),
+    ("olivedrab", 0x6b8e23),
+    ("orange", 0xffa500),
+    ("orangered", 0xff4500),
+    ("orchid", 0xda70d6),
+    ("palegoldenrod", 0xeee8aa),
+    ("palegreen", 0x98fb98),
+    ("paleturquoise", 0xafeeee),
+    ("palevioletred", 0xdb7093),
+    ("papayawhip", 0xffefd5),
+    ("peachpuff", 0xffdab9),
+    ("peru", 0xcd853f),
+    ("pink", 0xffc0cb),
+    ("plum", 0xdda0dd),
+    ("powderblue", 0xb0e0e6),
+    ("purple", 0x800080),
+    ("rebeccapurple", 0x663399),
+    ("red", 0xff0000),
+    ("rosybrown", 0xbc8f8f),
+    ("royalblue", 0x4169e1),
+    ("saddlebrown", 0x8b4513),
+    ("salmon", 0xfa8072),
+    ("sandybrown", 0xf4a460),
+    ("seagreen", 0x2e8b57),
+    ("seashell", 0xfff5ee),
+    ("sienna", 0xa0522d),
+    ("silver", 0xc0c0c0),
+    ("skyblue", 0x87ceeb),
+    ("slateblue", 0x6a5acd),
+    ("slategray", 0x708090),
+    ("slategrey", 0x708090),
+    ("snow", 0xfffafa),
+    ("springgreen", 0x00ff7f),
+    ("steelblue", 0x4682b4),
+    ("tan", 0xd2b48c),
+    ("teal", 0x008080),
+    ("thistle", 0xd8bfd8),
+    ("tomato", 0xff6347),
+    ("turquoise", 0x40e0d0),
+    ("violet", 0xee82ee),
+    ("wheat", 0xf5deb3),
+    ("white", 0xffffff),
+    ("whitesmoke", 0xf5f5f5),
+    ("yellow", 0xffff00),
+    ("yellowgreen", 0x9acd32),
+];
+
+#[cfg(test)]
+mod test {
+    use super::Color;
+
+    #[test]
+    fn parses_hex_names_and_hsv() {
+        assert_eq!("#ff8800".parse(), Ok(Color::rgb(255, 136, 0)));
+        assert_eq!("#F80".parse(), Ok(Color::rgb(255, 136, 0)));
+        assert_eq!("orange".parse(), Ok(Color::rgb(255, 165, 0)));
+        assert_eq!("Dark Orange".parse(), Ok(Color::rgb(255, 140, 0)));
+        assert_eq!("hsv(30, 1.0, 1.0)".parse(), Ok(Color::rgb(255, 128, 0)));
+        assert_eq!("hsv(240, 1, 0.5)".parse(), Ok(Color::rgb(0, 0, 128)));
+        assert_eq!("hsv(0, 0, 1)".parse(), Ok(Color::rgb(255, 255, 255)));
+
+        assert!("#ff88".parse::<Color>().is_err());
+        assert!("hsv(30, 2.0, 1.0)".parse::<Color>().is_err());
+        assert!("blurple".parse::<Color>().is_err());
+    }
+
+    #[derive(serde::Deserialize)]
+    struct Led {
+        color: Color,
+    }
+
+    #[test]
+    fn deserialises_arrays_and_strings() {
+        let led: Led = toml::from_str("color = [255, 0, 0]").unwrap();
+        assert_eq!(led.color, Color::rgb(255, 0, 0));
+        let led: Led = toml::from_str("color = \"#00ff00\"").unwrap();
+        assert_eq!(led.color, Color::rgb(0, 255, 0));
+
+        let error = toml::from_str::<Led>("color = \"blurple\"")
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(error.contains("`blurple`"), "{}", error);
+        assert!(error.contains("key `color`"), "{}", error);
+        assert!(toml::from_str::<Led>("color = [255, 0]").is_err());
+        assert!(toml::from_str::<Led>("color = [255, 0, 0, 0]").is_err());
+    }
+}
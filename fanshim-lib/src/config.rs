@@ -1,8 +1,20 @@
+use crate::color::Color;
+use crate::dimmer::Dimming;
+use crate::filter::FilterConfig;
+use crate::hooks::Event;
+use crate::led::{BrightnessCurve, Output};
+use crate::schedule::Window;
+use crate::status::Rule;
 use crate::{MilliCelsius, MILLI_CELSIUS_IN_CELSIUS};
+use log::{warn, LevelFilter};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::Read;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
+use toml::value::{Table, Value};
 
 #[derive(serde::Deserialize, Debug)]
 #[serde(default)]
@@ -10,6 +22,13 @@ pub struct General {
     pub milliseconds_delay_between_readings: u64,
     pub number_of_readings_before_action: i32,
     pub output_debug_messages: bool,
+    pub exit_state: ExitState,
+    /// Profile used unless another one is requested.
+    pub default_profile: Option<String>,
+    /// File holding the profile requested with `fanshim-rs profile`.
+    pub profile_file: Option<PathBuf>,
+    /// Number of APA102 pixels chained to the LED pins. The Fan SHIM has one.
+    pub number_of_led_pixels: usize,
 }
 
 impl Default for General {
@@ -18,15 +37,58 @@ impl Default for General {
             milliseconds_delay_between_readings: 500,
             number_of_readings_before_action: 3,
             output_debug_messages: false,
+            exit_state: ExitState::default(),
+            default_profile: None,
+            profile_file: None,
+            number_of_led_pixels: 1,
         }
     }
 }
 
-#[derive(serde::Deserialize, Debug)]
+/// What the fan and LED are left doing when the driver exits or panics.
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ExitState {
+    /// Fan and LED off.
+    #[default]
+    Off,
+    /// Fan at full speed and LED off, so the CPU stays cooled without the driver.
+    FanOn,
+    /// Fan and LED are left as they are.
+    Unchanged,
+}
+
+impl ExitState {
+    pub(crate) fn from_u8(value: u8) -> Self {
+        match value {
+            x if x == ExitState::FanOn as u8 => ExitState::FanOn,
+            x if x == ExitState::Unchanged as u8 => ExitState::Unchanged,
+            _ => ExitState::Off,
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
 #[serde(default)]
 pub struct Fan {
     pub enable_fan_at_degrees_celsius: i32,
     pub disable_fan_at_degrees_celsius: i32,
+    pub min_on_seconds: u64,
+    pub min_off_seconds: u64,
+    /// 0 disables the limit.
+    pub max_changes_per_hour: u32,
+    /// Value from 0.0 to 1.0.
+    pub max_fan_speed: f32,
+    /// Value from 0.0 to 1.0. Lower speeds turn the fan off.
+    pub min_fan_speed: f32,
+    /// 0 disables the kick-start.
+    pub kick_start_milliseconds: u64,
+    /// 0.0 changes the speed at once.
+    pub ramp_up_percent_per_second: f32,
+    /// 0.0 changes the speed at once.
+    pub ramp_down_percent_per_second: f32,
+    /// Rise of the CPU load between two readings that turns the fan on early. 0.0 disables it.
+    pub preemptive_load_increase: f32,
 }
 
 impl Default for Fan {
@@ -34,38 +96,61 @@ impl Default for Fan {
         Self {
             enable_fan_at_degrees_celsius: 65,
             disable_fan_at_degrees_celsius: 55,
+            min_on_seconds: 0,
+            min_off_seconds: 0,
+            max_changes_per_hour: 0,
+            max_fan_speed: 1.0,
+            min_fan_speed: 0.0,
+            kick_start_milliseconds: 0,
+            ramp_up_percent_per_second: 0.0,
+            ramp_down_percent_per_second: 0.0,
+            preemptive_load_increase: 0.0,
         }
     }
 }
 
-#[derive(serde::Deserialize, Debug)]
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
 #[serde(default)]
 pub struct Led {
     pub led_brightness: f32,
+    /// Exponent applied to each colour channel. 1.0 sends colours unchanged.
+    pub gamma: f32,
+    pub brightness_curve: BrightnessCurve,
+    /// Scales the colour channels to make up brightness between the 32 levels of the LED.
+    pub fine_brightness: bool,
+    /// Seconds without a change after which the LED turns off, 0 to keep it on.
+    pub off_after_idle_seconds: u64,
     pub fan_status: LedFanStatus,
+    /// Brightness scales by time of day.
+    pub dimming: Vec<Dimming>,
 }
 
 impl Default for Led {
     fn default() -> Self {
         Self {
             led_brightness: 0.1,
+            gamma: 1.0,
+            brightness_curve: BrightnessCurve::default(),
+            fine_brightness: false,
+            off_after_idle_seconds: 0,
             fan_status: LedFanStatus::default(),
+            dimming: Vec::new(),
         }
     }
 }
 
-#[derive(serde::Deserialize, Debug)]
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
 #[serde(default)]
 pub struct LedFanStatus {
-    pub led_on_color: (u8, u8, u8),
-    pub led_off_color: (u8, u8, u8),
+    pub led_on_color: Color,
+    pub led_off_color: Color,
 }
 
 impl Default for LedFanStatus {
     fn default() -> Self {
         Self {
-            led_on_color: (255, 0, 0),
-            led_off_color: (0, 255, 0),
+            led_on_color: Color::rgb(255, 0, 0),
+            led_off_color: Color::rgb(0, 255, 0),
         }
     }
 }
@@ -86,24 +171,379 @@ impl Default for LedTemperatureStatus {
     }
 }
 
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct Controller {
+    pub filter: FilterKind,
+    /// Number of readings used by the `moving_average` and `median` filters.
+    pub filter_window: usize,
+    /// Weight of the newest reading for the `exponential_moving_average` filter, from 0.0 to 1.0.
+    pub filter_alpha: f32,
+    /// Largest change let through by the `rate_limit` filter.
+    pub filter_max_degrees_celsius_per_second: f32,
+}
+
+impl Default for Controller {
+    fn default() -> Self {
+        Self {
+            filter: FilterKind::default(),
+            filter_window: 5,
+            filter_alpha: 0.3,
+            filter_max_degrees_celsius_per_second: 0.5,
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterKind {
+    #[default]
+    None,
+    MovingAverage,
+    ExponentialMovingAverage,
+    Median,
+    RateLimit,
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Logging {
+    /// Most verbose level that is logged, e.g. "info" or "debug".
+    pub level: LevelFilter,
+    pub target: LogTarget,
+    /// Log file used by the `file` target.
+    pub file: PathBuf,
+    /// Size at which the log file is rotated.
+    pub max_file_size_kilobytes: u64,
+    /// Number of rotated log files that are kept besides the current one.
+    pub max_rotated_files: u32,
+    /// Levels for individual modules, overriding `level`. Keys are module paths like "fanshim_lib".
+    pub modules: BTreeMap<String, LevelFilter>,
+}
+
+impl Default for Logging {
+    fn default() -> Self {
+        Self {
+            level: LevelFilter::Info,
+            target: LogTarget::default(),
+            file: PathBuf::from("/var/log/fanshim-rs.log"),
+            max_file_size_kilobytes: 1024,
+            max_rotated_files: 3,
+            modules: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum LogTarget {
+    #[default]
+    Stderr,
+    /// The systemd journal's native protocol, including structured `FANSHIM_*` fields.
+    Journald,
+    Syslog,
+    File,
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct History {
+    /// Number of entries kept in memory.
+    pub capacity: usize,
+    /// Minimum seconds between two recorded readings. Fan actions are always recorded.
+    pub seconds_between_readings: u64,
+    /// CSV file the history is kept in across restarts. Not persisted if unset.
+    pub file: Option<PathBuf>,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            capacity: 8640,
+            seconds_between_readings: 10,
+            file: None,
+        }
+    }
+}
+
+/// Thermal model used by the `sim` backend.
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Simulation {
+    pub ambient_degrees_celsius: f64,
+    /// Synthetic CPU load, from 0.0 (idle) to 1.0 (fully loaded).
+    pub cpu_load: f64,
+    /// How fast the CPU heats up at full load.
+    pub heating_degrees_celsius_per_second: f64,
+    /// Fraction of the difference to the ambient temperature lost every second with the fan off.
+    pub passive_cooling_per_second: f64,
+    /// How much faster the CPU cools with the fan on; 1.5 cools 2.5 times as fast.
+    pub fan_cooling_factor: f64,
+    /// Simulated seconds per real second. 0.0 runs as fast as possible.
+    pub time_scale: f64,
+}
+
+impl Default for Simulation {
+    fn default() -> Self {
+        Self {
+            ambient_degrees_celsius: 25.0,
+            cpu_load: 0.8,
+            heating_degrees_celsius_per_second: 0.6,
+            passive_cooling_per_second: 0.01,
+            fan_cooling_factor: 1.5,
+            time_scale: 1.0,
+        }
+    }
+}
+
+/// Detection of a fan that runs but does not cool, as the Fan SHIM can not report its speed.
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Health {
+    /// Minutes the fan has to run at full speed before its effect is judged. 0 disables the check.
+    pub minutes_at_full_speed: u64,
+    /// The fan is suspected to have failed if the temperature still rises at least this fraction
+    /// as fast as it did with the fan off.
+    pub rise_ratio: f64,
+    /// Colour the LED flashes while the fan is suspected to have failed.
+    pub led_color: Color,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self {
+            minutes_at_full_speed: 10,
+            rise_ratio: 0.8,
+            led_color: Color::rgb(255, 0, 255),
+        }
+    }
+}
+
+/// Checks of the throttle flags the Raspberry Pi firmware reports.
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Throttle {
+    /// 0 disables the checks.
+    pub seconds_between_checks: u64,
+    /// LED colour while the supply voltage is too low. The fan colours are kept if unset.
+    pub under_voltage_led_color: Option<Color>,
+    /// LED colour while the CPU is throttled, frequency capped or at its soft temperature limit.
+    /// The fan colours are kept if unset.
+    pub throttled_led_color: Option<Color>,
+}
+
+impl Default for Throttle {
+    fn default() -> Self {
+        Self {
+            seconds_between_checks: 10,
+            under_voltage_led_color: None,
+            throttled_led_color: None,
+        }
+    }
+}
+
+/// Commands run on state changes, with `sh -c`.
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Hooks {
+    pub on_fan_on: Option<String>,
+    pub on_fan_off: Option<String>,
+    /// Run when the fan is suspected to have failed, the firmware reports throttling or a
+    /// status rule becomes active, while none of them was before.
+    pub on_alarm: Option<String>,
+    pub on_alarm_clear: Option<String>,
+    pub on_button_long_press: Option<String>,
+    /// Commands still running after this many seconds are killed. 0 lets them run.
+    pub timeout_seconds: u64,
+}
+
+impl Default for Hooks {
+    fn default() -> Self {
+        Self {
+            on_fan_on: None,
+            on_fan_off: None,
+            on_alarm: None,
+            on_alarm_clear: None,
+            on_button_long_press: None,
+            timeout_seconds: 10,
+        }
+    }
+}
+
+impl Hooks {
+    /// The command configured for `event`, if any.
+    pub fn command(&self, event: Event) -> Option<&str> {
+        match event {
+            Event::FanOn => &self.on_fan_on,
+            Event::FanOff => &self.on_fan_off,
+            Event::Alarm => &self.on_alarm,
+            Event::AlarmClear => &self.on_alarm_clear,
+            Event::ButtonLongPress => &self.on_button_long_press,
+        }
+        .as_deref()
+    }
+}
+
+/// HTTP POST notifications, sent when built with the `webhooks` feature.
+#[derive(serde::Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct Webhook {
+    /// No notifications are sent if unset.
+    pub url: Option<String>,
+    pub events: Vec<Event>,
+    /// Attempts after the first one failed, waiting twice as long before each.
+    pub retries: u32,
+    pub retry_delay_seconds: u64,
+    /// Notifications beyond this many within an hour are dropped. 0 disables the limit.
+    pub max_per_hour: u32,
+    pub timeout_seconds: u64,
+}
+
+impl Default for Webhook {
+    fn default() -> Self {
+        Self {
+            url: None,
+            events: vec![Event::Alarm, Event::AlarmClear],
+            retries: 3,
+            retry_delay_seconds: 5,
+            max_per_hour: 10,
+            timeout_seconds: 10,
+        }
+    }
+}
+
+/// A config section, along with the keys it was given, so a profile can override single keys.
+#[derive(Debug, Clone, Default)]
+pub struct Section<T> {
+    pub keys: Table,
+    pub values: T,
+}
+
+impl<'de, T: DeserializeOwned> serde::Deserialize<'de> for Section<T> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let keys = Table::deserialize(deserializer)?;
+        let values = Value::Table(keys.clone())
+            .try_into()
+            .map_err(serde::de::Error::custom)?;
+        Ok(Self { keys, values })
+    }
+}
+
+impl<T: Serialize> Section<T> {
+    /// A section with every key set.
+    pub fn from_values(values: T) -> Self {
+        let keys = match Value::try_from(&values) {
+            Ok(Value::Table(keys)) => keys,
+            _ => Table::new(),
+        };
+        Self { keys, values }
+    }
+}
+
+impl<T: DeserializeOwned + Clone> Section<T> {
+    /// These values, with the keys given in `overrides` replacing them.
+    pub fn overlay(&self, overrides: &Section<T>) -> T {
+        let mut keys = self.keys.clone();
+        merge_tables(&mut keys, &overrides.keys);
+        Value::Table(keys).try_into().unwrap_or_else(|e| {
+            warn!(
+                "Unable to apply the profile, using the top level section: {}",
+                e
+            );
+            self.values.clone()
+        })
+    }
+}
+
+/// Copies the keys of `overrides` into `base`, descending into tables both have.
+fn merge_tables(base: &mut Table, overrides: &Table) {
+    for (key, value) in overrides {
+        match (base.get_mut(key), value) {
+            (Some(Value::Table(base)), Value::Table(overrides)) => merge_tables(base, overrides),
+            _ => {
+                base.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+/// A `[profiles.<name>]` section. Keys left out are taken from the top level of the config file.
+#[derive(serde::Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Profile {
+    pub fan: Option<Section<Fan>>,
+    pub led: Option<Section<Led>>,
+    pub controller: Option<Section<Controller>>,
+}
+
 #[derive(serde::Deserialize, Debug, Default)]
 #[serde(default)]
 pub struct FanshimInterimConfig {
     pub fan: Fan,
     pub general: General,
     pub led: Led,
+    pub controller: Controller,
+    pub logging: Logging,
+    pub history: History,
+    pub simulation: Simulation,
+    pub health: Health,
+    pub throttle: Throttle,
+    pub hooks: Hooks,
+    pub webhook: Webhook,
+    pub schedule: Vec<Window>,
+    pub status: Vec<Rule>,
+    pub profiles: BTreeMap<String, Profile>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct FanshimConfig {
     pub delay_between_readings: Duration,
     pub number_of_readings_before_action: i32,
     pub output_debug_messages: bool,
+    pub exit_state: ExitState,
     pub enable_fan_at_temperature: MilliCelsius,
     pub disable_fan_at_temperature: MilliCelsius,
+    pub min_on_time: Duration,
+    pub min_off_time: Duration,
+    /// 0 disables the limit.
+    pub max_changes_per_hour: u32,
+    pub max_fan_speed: f32,
+    pub min_fan_speed: f32,
+    /// Time at full speed before the fan drops to a partial speed from standstill.
+    pub kick_start_time: Duration,
+    /// Change of the duty cycle per second while speeding up, 0.0 to change at once.
+    pub ramp_up_per_second: f32,
+    /// Change of the duty cycle per second while slowing down, 0.0 to change at once.
+    pub ramp_down_per_second: f32,
+    /// Rise of the CPU load between two readings that turns the fan on before the temperature
+    /// catches up, from 0.0 to 1.0. 0.0 disables it.
+    pub preemptive_load_increase: f32,
     pub led_brightness: f32,
-    pub led_on_color: (u8, u8, u8),
-    pub led_off_color: (u8, u8, u8),
+    pub led_pixels: usize,
+    pub led_output: Output,
+    /// Time without a change after which the LED turns off, 0 to keep it on.
+    pub led_off_after_idle: Duration,
+    pub led_dimming: Vec<Dimming>,
+    pub led_on_color: Color,
+    pub led_off_color: Color,
+    pub filter: FilterConfig,
+    pub logging: Logging,
+    pub history: History,
+    pub simulation: Simulation,
+    pub health: Health,
+    pub throttle: Throttle,
+    pub hooks: Hooks,
+    pub webhook: Webhook,
+    /// Windows that override the settings above, first match wins.
+    pub schedule: Vec<Window>,
+    /// Conditions of the system shown on the LED.
+    pub status: Vec<Rule>,
+    /// Name of the active profile, if any.
+    pub profile: Option<String>,
+    pub profiles: BTreeMap<String, Profile>,
+    pub profile_file: Option<PathBuf>,
+    /// The fan, LED and controller sections at the top level of the config file.
+    pub top_level: Profile,
 }
 
 impl Default for FanshimConfig {
@@ -114,23 +554,163 @@ impl Default for FanshimConfig {
 
 impl From<FanshimInterimConfig> for FanshimConfig {
     fn from(f: FanshimInterimConfig) -> Self {
-        Self {
+        let mut config = Self {
             delay_between_readings: Duration::from_millis(
                 f.general.milliseconds_delay_between_readings,
             ),
             number_of_readings_before_action: 3,
             output_debug_messages: f.general.output_debug_messages,
-            enable_fan_at_temperature: MilliCelsius(
-                f.fan.enable_fan_at_degrees_celsius * MILLI_CELSIUS_IN_CELSIUS,
-            ),
-            disable_fan_at_temperature: MilliCelsius(
-                f.fan.disable_fan_at_degrees_celsius * MILLI_CELSIUS_IN_CELSIUS,
-            ),
-            led_brightness: f.led.led_brightness,
-            led_on_color: f.led.fan_status.led_on_color,
-            led_off_color: f.led.fan_status.led_off_color,
+            exit_state: f.general.exit_state,
+            enable_fan_at_temperature: MilliCelsius(0),
+            disable_fan_at_temperature: MilliCelsius(0),
+            min_on_time: Duration::from_secs(0),
+            min_off_time: Duration::from_secs(0),
+            max_changes_per_hour: 0,
+            max_fan_speed: 1.0,
+            min_fan_speed: 0.0,
+            kick_start_time: Duration::from_secs(0),
+            ramp_up_per_second: 0.0,
+            ramp_down_per_second: 0.0,
+            preemptive_load_increase: 0.0,
+            led_brightness: 0.0,
+            led_pixels: f.general.number_of_led_pixels,
+            led_output: Output::default(),
+            led_off_after_idle: Duration::from_secs(0),
+            led_dimming: Vec::new(),
+            led_on_color: Color::BLACK,
+            led_off_color: Color::BLACK,
+            filter: FilterConfig::None,
+            logging: Logging {
+                level: if f.general.output_debug_messages {
+                    f.logging.level.max(LevelFilter::Debug)
+                } else {
+                    f.logging.level
+                },
+                ..f.logging
+            },
+            history: f.history,
+            simulation: f.simulation,
+            health: f.health,
+            throttle: f.throttle,
+            hooks: f.hooks,
+            webhook: f.webhook,
+            schedule: f.schedule,
+            status: f.status,
+            profile: None,
+            profiles: f.profiles,
+            profile_file: f.general.profile_file,
+            top_level: Profile {
+                fan: Some(Section::from_values(f.fan)),
+                led: Some(Section::from_values(f.led)),
+                controller: Some(Section::from_values(f.controller)),
+            },
+        };
+        config.apply_profile(None);
+
+        match f.general.default_profile {
+            Some(name) => config.with_profile(Some(&name)).unwrap_or_else(|| {
+                warn!(
+                    "Default profile '{}' does not exist. Using no profile.",
+                    name
+                );
+                config
+            }),
+            None => config,
+        }
+    }
+}
+
+impl FanshimConfig {
+    /// This config with the sections of profile `name`, or with the top level sections if `None`.
+    /// Returns `None` if there is no such profile.
+    pub fn with_profile(&self, name: Option<&str>) -> Option<Self> {
+        let profile = match name {
+            Some(name) => Some(self.profiles.get(name)?.clone()),
+            None => None,
+        };
+        let mut config = self.clone();
+        config.apply_profile(profile.as_ref());
+        config.profile = name.map(str::to_owned);
+        Some(config)
+    }
+
+    /// The profile after the active one in alphabetical order, wrapping around.
+    pub fn next_profile(&self) -> Option<&str> {
+        let mut names = self.profiles.keys().map(String::as_str);
+        match &self.profile {
+            Some(active) => names
+                .clone()
+                .skip_while(|&n| n != active)
+                .nth(1)
+                .or_else(|| names.next()),
+            None => names.next(),
         }
     }
+
+    fn apply_profile(&mut self, profile: Option<&Profile>) {
+        let top_level = &self.top_level;
+        let fan = section(top_level.fan.as_ref(), profile.and_then(|p| p.fan.as_ref()));
+        let led = section(top_level.led.as_ref(), profile.and_then(|p| p.led.as_ref()));
+        let controller = section(
+            top_level.controller.as_ref(),
+            profile.and_then(|p| p.controller.as_ref()),
+        );
+
+        self.enable_fan_at_temperature =
+            MilliCelsius(fan.enable_fan_at_degrees_celsius * MILLI_CELSIUS_IN_CELSIUS);
+        self.disable_fan_at_temperature =
+            MilliCelsius(fan.disable_fan_at_degrees_celsius * MILLI_CELSIUS_IN_CELSIUS);
+        self.min_on_time = Duration::from_secs(fan.min_on_seconds);
+        self.min_off_time = Duration::from_secs(fan.min_off_seconds);
+        self.max_changes_per_hour = fan.max_changes_per_hour;
+        self.max_fan_speed = fan.max_fan_speed;
+        self.min_fan_speed = fan.min_fan_speed;
+        self.kick_start_time = Duration::from_millis(fan.kick_start_milliseconds);
+        self.ramp_up_per_second = fan.ramp_up_percent_per_second / 100.0;
+        self.ramp_down_per_second = fan.ramp_down_percent_per_second / 100.0;
+        self.preemptive_load_increase = fan.preemptive_load_increase;
+        self.led_brightness = led.led_brightness;
+        self.led_output = Output {
+            gamma: led.gamma,
+            brightness_curve: led.brightness_curve,
+            fine_brightness: led.fine_brightness,
+        };
+        self.led_on_color = led.fan_status.led_on_color;
+        self.led_off_color = led.fan_status.led_off_color;
+        self.led_off_after_idle = Duration::from_secs(led.off_after_idle_seconds);
+        self.led_dimming = led.dimming;
+        self.filter = filter_config(&controller);
+    }
+}
+
+/// The values of a section at the top level, with the keys given by the profile replacing them.
+fn section<T: DeserializeOwned + Clone + Default>(
+    top_level: Option<&Section<T>>,
+    profile: Option<&Section<T>>,
+) -> T {
+    match (top_level, profile) {
+        (Some(top_level), Some(profile)) => top_level.overlay(profile),
+        (Some(section), None) | (None, Some(section)) => section.values.clone(),
+        (None, None) => T::default(),
+    }
+}
+
+fn filter_config(c: &Controller) -> FilterConfig {
+    match c.filter {
+        FilterKind::None => FilterConfig::None,
+        FilterKind::MovingAverage => FilterConfig::MovingAverage {
+            window: c.filter_window,
+        },
+        FilterKind::ExponentialMovingAverage => FilterConfig::ExponentialMovingAverage {
+            alpha: c.filter_alpha,
+        },
+        FilterKind::Median => FilterConfig::Median {
+            window: c.filter_window,
+        },
+        FilterKind::RateLimit => FilterConfig::RateLimit {
+            degrees_per_second: c.filter_max_degrees_celsius_per_second,
+        },
+    }
 }
 
 pub fn read_config_file(file_location: &Path) -> crate::Result<FanshimConfig> {
@@ -142,3 +722,156 @@ pub fn read_config_file(file_location: &Path) -> crate::Result<FanshimConfig> {
 
     Ok(FanshimConfig::from(config))
 }
+
+#[cfg(test)]
+mod test {
+    use super::{FanshimConfig, FanshimInterimConfig};
+    use crate::color::Color;
+    use crate::led::Pixel;
+    use crate::MilliCelsius;
+
+    fn config(toml: &str) -> FanshimConfig {
+        FanshimConfig::from(toml::from_str::<FanshimInterimConfig>(toml).unwrap())
+    }
+
+    const PROFILES: &str = r#"
+        [general]
+        default_profile = "silent"
+
+        [fan]
+        enable_fan_at_degrees_celsius = 60
+
+        [led]
+        led_brightness = 0.5
+
+        [profiles.silent.fan]
+        enable_fan_at_degrees_celsius = 75
+        max_fan_speed = 0.5
+
+        [profiles.silent.led]
+        led_brightness = 0.0
+
+        [profiles.cool.fan]
+        enable_fan_at_degrees_celsius = 50
+    "#;
+
+    #[test]
+    fn profiles_fall_back_to_top_level_keys() {
+        let c = config(
+            r#"
+            [general]
+            default_profile = "silent"
+
+            [fan]
+            disable_fan_at_degrees_celsius = 40
+
+            [led]
+            led_brightness = 0.5
+
+            [led.fan_status]
+            led_on_color = [0, 0, 255]
+
+            [profiles.silent.fan]
+            enable_fan_at_degrees_celsius = 75
+
+            [profiles.silent.led]
+            led_brightness = 0.0
+            "#,
+        );
+
+        assert_eq!(c.enable_fan_at_temperature, MilliCelsius(75_000));
+        assert_eq!(c.disable_fan_at_temperature, MilliCelsius(40_000));
+        assert_eq!(c.led_brightness, 0.0);
+        assert_eq!(c.led_on_color, Color::rgb(0, 0, 255));
+        assert_eq!(c.led_off_color, Color::rgb(0, 255, 0));
+    }
+
+    #[test]
+    fn profiles_fall_back_to_top_level_sections() {
+        let c = config(PROFILES);
+        assert_eq!(c.profile.as_deref(), Some("silent"));
+        assert_eq!(c.enable_fan_at_temperature, MilliCelsius(75_000));
+        assert_eq!(c.max_fan_speed, 0.5);
+        assert_eq!(c.led_brightness, 0.0);
+
+        let cool = c.with_profile(Some("cool")).unwrap();
+        assert_eq!(cool.enable_fan_at_temperature, MilliCelsius(50_000));
+        assert_eq!(cool.max_fan_speed, 1.0);
+        assert_eq!(cool.led_brightness, 0.5);
+
+        let none = c.with_profile(None).unwrap();
+        assert_eq!(none.profile, None);
+        assert_eq!(none.enable_fan_at_temperature, MilliCelsius(60_000));
+
+        assert!(c.with_profile(Some("loud")).is_none());
+    }
+
+    #[test]
+    fn next_profile_cycles_alphabetically() {
+        let c = config(PROFILES);
+        assert_eq!(c.next_profile(), Some("cool"));
+
+        let cool = c.with_profile(c.next_profile()).unwrap();
+        assert_eq!(cool.next_profile(), Some("silent"));
+
+        assert_eq!(c.with_profile(None).unwrap().next_profile(), Some("cool"));
+        assert_eq!(config("").next_profile(), None);
+    }
+
+    #[test]
+    fn unknown_default_profile_is_ignored() {
+        let c = config("[general]\ndefault_profile = \"loud\"\n");
+        assert_eq!(c.profile, None);
+        assert_eq!(
+            c.enable_fan_at_temperature,
+            FanshimConfig::default().enable_fan_at_temperature
+        );
+    }
+
+    #[test]
+    fn led_output_is_unchanged_without_the_output_keys() {
+        let c = config("[led]\nled_brightness = 0.1\n");
+        let pixel = Pixel {
+            color: Color::rgb(255, 128, 0),
+            brightness: 0.1,
+        };
+
+        // The brightness level rounded down and the colour as it is, as sent before.
+        assert_eq!(c.led_output.encode(pixel), (Color::rgb(255, 128, 0), 3));
+    }
+
+    #[test]
+    fn colours_accept_every_format() {
+        let c = config(
+            r##"
+            [led.fan_status]
+            led_on_color = "hsv(30, 1.0, 1.0)"
+            led_off_color = "#00ff80"
+
+            [health]
+            led_color = "purple"
+
+            [throttle]
+            under_voltage_led_color = [255, 255, 0]
+            "##,
+        );
+        assert_eq!(c.led_on_color, Color::rgb(255, 128, 0));
+        assert_eq!(c.led_off_color, Color::rgb(0, 255, 128));
+        assert_eq!(c.health.led_color, Color::rgb(128, 0, 128));
+        assert_eq!(
+            c.throttle.under_voltage_led_color,
+            Some(Color::rgb(255, 255, 0))
+        );
+
+        let error =
+            toml::from_str::<FanshimInterimConfig>("[led.fan_status]\nled_on_color = \"#ff00\"\n")
+                .err()
+                .unwrap()
+                .to_string();
+        assert!(
+            error.contains("key `led.fan_status.led_on_color`"),
+            "{}",
+            error
+        );
+    }
+}
@@ -2,9 +2,11 @@ use crate::MilliCelsius;
 use std::io::Read;
 use std::{fs, io};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Hertz(pub i64);
 
+const HERTZ_IN_KILOHERTZ: i64 = 1000;
+
 fn read_file(path: &str) -> io::Result<String> {
     let mut s = String::new();
     fs::File::open(path)
@@ -18,11 +20,124 @@ pub fn get_cpu_temperature() -> MilliCelsius {
             .or_else(|_| read_file("/sys/class/hwmon/hwmon0/temp1_input"))
             .and_then(|data| match data.trim().parse::<i32>() {
                 Ok(x) => Ok(x),
-                Err(_) => Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    "Could not parse cpu temperature",
-                )),
+                Err(_) => Err(io::Error::other("Could not parse cpu temperature")),
             })
             .expect("unable to parse cpu temperature"),
     )
 }
+
+/// Time all CPUs together spent busy and in total since boot, in clock ticks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CpuTimes {
+    pub busy: u64,
+    pub total: u64,
+}
+
+impl CpuTimes {
+    /// Fraction of the time between `earlier` and `self` the CPUs were busy, from 0.0 to 1.0.
+    pub fn load_since(&self, earlier: &CpuTimes) -> Option<f32> {
+        let total = self.total.checked_sub(earlier.total)?;
+        let busy = self.busy.checked_sub(earlier.busy)?;
+        if total == 0 {
+            return None;
+        }
+        Some((busy as f64 / total as f64).min(1.0) as f32)
+    }
+}
+
+/// Parses the `cpu` line of `/proc/stat`.
+pub fn parse_cpu_times(stat: &str) -> Option<CpuTimes> {
+    let line = stat.lines().find(|l| l.starts_with("cpu "))?;
+    // user, nice, system, idle, iowait, irq, softirq and steal. Guest time is part of user time.
+    let times: Vec<u64> = line
+        .split_whitespace()
+        .skip(1)
+        .take(8)
+        .map(str::parse)
+        .collect::<Result<_, _>>()
+        .ok()?;
+    if times.len() < 4 {
+        return None;
+    }
+    let total = times.iter().sum();
+    let idle = times[3] + times.get(4).copied().unwrap_or(0);
+    Some(CpuTimes {
+        busy: total - idle,
+        total,
+    })
+}
+
+pub fn get_cpu_times() -> io::Result<CpuTimes> {
+    parse_cpu_times(&read_file("/proc/stat")?)
+        .ok_or_else(|| io::Error::other("Could not parse /proc/stat"))
+}
+
+/// Measures the CPU load between two calls of `load`.
+#[derive(Default)]
+pub struct LoadMeter {
+    last: Option<CpuTimes>,
+}
+
+impl LoadMeter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load since the last call, or `None` on the first call.
+    pub fn load(&mut self) -> io::Result<Option<f32>> {
+        let times = get_cpu_times()?;
+        let load = self.last.and_then(|last| times.load_since(&last));
+        self.last = Some(times);
+        Ok(load)
+    }
+}
+
+/// Highest current frequency of all CPUs.
+pub fn get_cpu_frequency() -> io::Result<Hertz> {
+    let mut highest = None;
+    for entry in fs::read_dir("/sys/devices/system/cpu")? {
+        let path = entry?.path();
+        let is_cpu = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .and_then(|n| n.strip_prefix("cpu"))
+            .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()));
+        if !is_cpu {
+            continue;
+        }
+        if let Ok(data) = fs::read_to_string(path.join("cpufreq/scaling_cur_freq")) {
+            if let Ok(khz) = data.trim().parse::<i64>() {
+                highest = highest.max(Some(khz));
+            }
+        }
+    }
+    highest
+        .map(|khz| Hertz(khz * HERTZ_IN_KILOHERTZ))
+        .ok_or_else(|| io::Error::other("No CPU reports its frequency"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_cpu_times, CpuTimes};
+
+    #[test]
+    fn parses_proc_stat_and_computes_load() {
+        let before = parse_cpu_times(
+            "cpu  100 0 100 700 100 0 0 0 0 0\n\
+             cpu0 50 0 50 350 50 0 0 0 0 0\n",
+        )
+        .unwrap();
+        assert_eq!(
+            before,
+            CpuTimes {
+                busy: 200,
+                total: 1000
+            }
+        );
+
+        let after = parse_cpu_times("cpu  400 0 100 800 100 0 0 0 0 0\n").unwrap();
+        assert_eq!(after.load_since(&before), Some(0.75));
+        assert_eq!(before.load_since(&after), None);
+        assert_eq!(parse_cpu_times("intr 1 2 3\n"), None);
+    }
+}
@@ -0,0 +1,179 @@
+use crate::color::Color;
+use crate::config::FanshimConfig;
+use crate::schedule::{self, TimeOfDay};
+use chrono::{NaiveDateTime, Timelike};
+use std::time::{Duration, SystemTime};
+
+/// A `[[led.dimming]]` section: scales the LED brightness from a time of day until the next
+/// section's time.
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq)]
+pub struct Dimming {
+    pub from: TimeOfDay,
+    pub brightness_scale: f32,
+}
+
+/// Colour and brightness of the LED.
+type Frame = (Color, f32);
+
+/// Decides what the LED shows, from what `main_loop` asks for.
+///
+/// The LED is turned off once nothing was asked for or woken for during `off_after_idle`, and
+/// turned back on by the next change or `wake`. The brightness is scaled by the time of day.
+pub struct Dimmer {
+    off_after_idle: Duration,
+    dimming: Vec<Dimming>,
+    requested: Option<Frame>,
+    shown: Option<Frame>,
+    active: bool,
+    last_active: Option<SystemTime>,
+}
+
+impl Dimmer {
+    pub fn new(config: &FanshimConfig) -> Self {
+        Self {
+            off_after_idle: config.led_off_after_idle,
+            dimming: config.led_dimming.clone(),
+            requested: None,
+            shown: None,
+            active: false,
+            last_active: None,
+        }
+    }
+
+    /// Takes the idle time and dimming of `config`, keeping what the LED shows.
+    pub fn reconfigure(&mut self, config: &FanshimConfig) {
+        self.off_after_idle = config.led_off_after_idle;
+        self.dimming = config.led_dimming.clone();
+    }
+
+    /// Takes what the LED already shows, such as set by `initial_setup`.
+    pub fn assume_shown(&mut self, color: Color, brightness: f32) {
+        self.requested = Some((color, brightness));
+        self.shown = self.requested;
+        self.active = true;
+    }
+
+    /// Asks for the LED to show `color` at `brightness`. A change wakes the LED.
+    pub fn request(&mut self, color: Color, brightness: f32) {
+        let frame = Some((color, brightness));
+        if frame != self.requested {
+            self.requested = frame;
+            self.active = true;
+        }
+    }
+
+    /// Wakes the LED, or keeps it on, without changing what it shows.
+    pub fn wake(&mut self) {
+        self.active = true;
+    }
+
+    /// Whether the LED is off for being idle at `now`.
+    pub fn is_idle(&self, now: SystemTime) -> bool {
+        self.off_after_idle > Duration::from_secs(0)
+            && self.last_active.is_some_and(|t| {
+                now.duration_since(t)
+                    .is_ok_and(|idle| idle >= self.off_after_idle)
+            })
+    }
+
+    /// What to send to the LED at `now`, if it differs from what the LED shows.
+    pub fn update(&mut self, now: SystemTime) -> Option<Frame> {
+        if self.active || self.last_active.is_none() {
+            self.active = false;
+            self.last_active = Some(now);
+        }
+
+        let frame = if self.is_idle(now) {
+            (Color::BLACK, 0.0)
+        } else {
+            let (color, brightness) = self.requested?;
+            let scale = brightness_scale(&self.dimming, schedule::local_time(now));
+            (color, brightness * scale)
+        };
+        if Some(frame) == self.shown {
+            None
+        } else {
+            self.shown = Some(frame);
+            Some(frame)
+        }
+    }
+}
+
+/// Scale of the section with the latest `from` at or before `time`, carried over from the
+/// previous day before the first one. 1.0 without sections.
+fn brightness_scale(dimming: &[Dimming], time: NaiveDateTime) -> f32 {
+    let minute = time.hour() * 60 + time.minute();
+    let from = |d: &&Dimming| d.from.minutes_since_midnight();
+    dimming
+        .iter()
+        .filter(|d| from(d) <= minute)
+        .max_by_key(from)
+        .or_else(|| dimming.iter().max_by_key(from))
+        .map_or(1.0, |d| d.brightness_scale.clamp(0.0, 1.0))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{brightness_scale, Dimmer, Dimming};
+    use crate::color::Color;
+    use crate::config::{FanshimConfig, FanshimInterimConfig};
+    use chrono::NaiveDate;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn dimming(toml: &str) -> Vec<Dimming> {
+        FanshimConfig::from(toml::from_str::<FanshimInterimConfig>(toml).unwrap()).led_dimming
+    }
+
+    #[test]
+    fn turns_off_when_idle_and_wakes_on_change() {
+        let mut dimmer = Dimmer::new(&FanshimConfig {
+            led_off_after_idle: Duration::from_secs(30),
+            ..FanshimConfig::default()
+        });
+        let red = Color::rgb(255, 0, 0);
+        let at = |s| UNIX_EPOCH + Duration::from_secs(s);
+
+        dimmer.assume_shown(red, 0.5);
+        assert_eq!(dimmer.update(at(0)), None);
+        // Asking for the same again is no change.
+        dimmer.request(red, 0.5);
+        assert_eq!(dimmer.update(at(20)), None);
+        assert_eq!(dimmer.update(at(30)), Some((Color::BLACK, 0.0)));
+        assert_eq!(dimmer.update(at(40)), None);
+
+        dimmer.wake();
+        assert_eq!(dimmer.update(at(50)), Some((red, 0.5)));
+        assert_eq!(dimmer.update(at(79)), None);
+        dimmer.request(Color::BLACK, 0.5);
+        assert_eq!(dimmer.update(at(80)), Some((Color::BLACK, 0.5)));
+        assert!(!dimmer.is_idle(at(109)));
+        assert!(dimmer.is_idle(at(110)));
+    }
+
+    #[test]
+    fn scales_brightness_by_time_of_day() {
+        let d = dimming(
+            r#"
+            [[led.dimming]]
+            from = "22:00"
+            brightness_scale = 0.2
+
+            [[led.dimming]]
+            from = "07:00"
+            brightness_scale = 1.0
+
+            [[led.dimming]]
+            from = "19:30"
+            brightness_scale = 0.5
+            "#,
+        );
+        let at = |h, m| NaiveDate::from_ymd(2020, 10, 2).and_hms(h, m, 0);
+
+        assert_eq!(brightness_scale(&d, at(3, 0)), 0.2);
+        assert_eq!(brightness_scale(&d, at(7, 0)), 1.0);
+        assert_eq!(brightness_scale(&d, at(19, 29)), 1.0);
+        assert_eq!(brightness_scale(&d, at(19, 30)), 0.5);
+        assert_eq!(brightness_scale(&d, at(23, 59)), 0.2);
+        assert_eq!(brightness_scale(&[], at(3, 0)), 1.0);
+    }
+}
@@ -2,8 +2,11 @@ use std::fmt::Formatter;
 #[derive(Debug)]
 pub enum FanshimError {
     IoError,
-    ConfigParseError,
+    /// Holds the parser's message, which names the offending key.
+    ConfigParseError(String),
     SetLoggerError,
+    GpioError,
+    LedError,
 }
 
 impl std::error::Error for FanshimError {}
@@ -12,15 +15,17 @@ impl std::fmt::Display for FanshimError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
             FanshimError::IoError => write!(f, "IoError"),
-            FanshimError::ConfigParseError => write!(f, "ConfigParseError"),
+            FanshimError::ConfigParseError(e) => write!(f, "ConfigParseError: {}", e),
             FanshimError::SetLoggerError => write!(f, "SetLoggerError"),
+            FanshimError::GpioError => write!(f, "GpioError"),
+            FanshimError::LedError => write!(f, "LedError"),
         }
     }
 }
 
 impl From<toml::de::Error> for FanshimError {
-    fn from(_: toml::de::Error) -> Self {
-        FanshimError::ConfigParseError
+    fn from(e: toml::de::Error) -> Self {
+        FanshimError::ConfigParseError(e.to_string())
     }
 }
 
@@ -35,3 +40,15 @@ impl From<log::SetLoggerError> for FanshimError {
         FanshimError::SetLoggerError
     }
 }
+
+impl From<rppal::gpio::Error> for FanshimError {
+    fn from(_: rppal::gpio::Error) -> Self {
+        FanshimError::GpioError
+    }
+}
+
+impl From<blinkt::Error> for FanshimError {
+    fn from(_: blinkt::Error) -> Self {
+        FanshimError::LedError
+    }
+}
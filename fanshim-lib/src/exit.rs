@@ -0,0 +1,83 @@
+use crate::config::ExitState;
+use crate::led::Led;
+use log::{error, info};
+use std::panic;
+use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Mutex, Once};
+use std::thread::{self, ThreadId};
+
+static PANIC_EXIT_STATE: AtomicU8 = AtomicU8::new(ExitState::Off as u8);
+static PANIC_LED_PIXELS: AtomicUsize = AtomicUsize::new(crate::led::FAN_SHIM_PIXELS);
+static INSTALL_PANIC_HOOK: Once = Once::new();
+/// The thread controlling the fan. Panics elsewhere leave the fan to it.
+static CONTROL_THREAD: Mutex<Option<ThreadId>> = Mutex::new(None);
+
+/// Puts the fan and the `led_pixels` pixels of the LED into `state`.
+///
+/// Errors are returned instead of panicking, so this is safe to call while already panicking.
+pub fn apply_exit_state(state: ExitState, led_pixels: usize) -> crate::Result<()> {
+    match state {
+        ExitState::Off => {
+            Led::new(led_pixels).show()?;
+            crate::fan::try_turn_fan_off()?;
+        }
+        ExitState::FanOn => {
+            Led::new(led_pixels).show()?;
+            crate::fan::try_turn_fan_full_on()?;
+        }
+        ExitState::Unchanged => {}
+    }
+    Ok(())
+}
+
+/// Makes a panic of the calling thread apply `state` after the previous panic hook has run.
+/// Panics of other threads, e.g. hook runners or the status monitor, only run the previous hook,
+/// as the calling thread keeps controlling the fan.
+///
+/// Can be called again, e.g. after reloading the config, to change the state.
+pub fn install_panic_hook(state: ExitState, led_pixels: usize) {
+    PANIC_EXIT_STATE.store(state as u8, Ordering::SeqCst);
+    PANIC_LED_PIXELS.store(led_pixels, Ordering::SeqCst);
+    record_control_thread();
+
+    INSTALL_PANIC_HOOK.call_once(|| {
+        let previous_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |panic_info| {
+            previous_hook(panic_info);
+            if !is_control_thread() {
+                return;
+            }
+
+            let state = ExitState::from_u8(PANIC_EXIT_STATE.load(Ordering::SeqCst));
+            info!("Panicked. Setting exit state: {:?}", state);
+            let led_pixels = PANIC_LED_PIXELS.load(Ordering::SeqCst);
+            if let Err(e) = apply_exit_state(state, led_pixels) {
+                error!("Unable to set exit state: {}", e);
+            }
+        }));
+    });
+}
+
+fn record_control_thread() {
+    let mut control = CONTROL_THREAD.lock().unwrap_or_else(|e| e.into_inner());
+    *control = Some(thread::current().id());
+}
+
+fn is_control_thread() -> bool {
+    let control = CONTROL_THREAD.lock().unwrap_or_else(|e| e.into_inner());
+    *control == Some(thread::current().id())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{is_control_thread, record_control_thread};
+    use std::thread;
+
+    #[test]
+    fn only_the_recording_thread_is_the_control_thread() {
+        record_control_thread();
+
+        assert!(is_control_thread());
+        assert!(!thread::spawn(is_control_thread).join().unwrap());
+    }
+}
@@ -1,9 +1,19 @@
+use crate::config::FanshimConfig;
 use rppal::gpio::Gpio;
 use rppal::gpio::Level::High;
 use rppal::gpio::Mode::Output;
+use rppal::gpio::OutputPin;
+use std::time::Duration;
 
 const FAN_ENABLED_PIN: u8 = 18;
 
+fn fan_pin() -> rppal::gpio::Result<OutputPin> {
+    let mut pin = Gpio::new()?.get(FAN_ENABLED_PIN)?.into_output();
+
+    pin.set_reset_on_drop(false);
+    Ok(pin)
+}
+
 pub fn fan_is_enabled() -> bool {
     let pin = Gpio::new()
         .expect("unable to get gpio")
@@ -14,34 +24,135 @@ pub fn fan_is_enabled() -> bool {
 }
 
 pub fn toggle_fan() {
-    let mut pin = Gpio::new()
-        .expect("unable to get gpio")
-        .get(FAN_ENABLED_PIN)
-        .expect("unable to get fan pin")
-        .into_output();
-
-    pin.set_reset_on_drop(false);
-    pin.toggle();
+    fan_pin().expect("unable to get fan pin").toggle();
 }
 
 pub fn turn_fan_off() {
-    let mut pin = Gpio::new()
-        .expect("unable to get gpio")
-        .get(FAN_ENABLED_PIN)
-        .expect("unable to get fan pin")
-        .into_output();
-
-    pin.set_reset_on_drop(false);
-    pin.set_low();
+    try_turn_fan_off().expect("unable to get fan pin");
 }
 
 pub fn turn_fan_full_on() {
-    let mut pin = Gpio::new()
-        .expect("unable to get gpio")
-        .get(FAN_ENABLED_PIN)
-        .expect("unable to get fan pin")
-        .into_output();
+    try_turn_fan_full_on().expect("unable to get fan pin");
+}
 
-    pin.set_reset_on_drop(false);
-    pin.set_high();
+/// Like `turn_fan_off`, but returns GPIO errors instead of panicking.
+pub fn try_turn_fan_off() -> rppal::gpio::Result<()> {
+    fan_pin()?.set_low();
+    Ok(())
+}
+
+/// Like `turn_fan_full_on`, but returns GPIO errors instead of panicking.
+pub fn try_turn_fan_full_on() -> rppal::gpio::Result<()> {
+    fan_pin()?.set_high();
+    Ok(())
+}
+
+/// Frequency of the software PWM used for fan speeds between off and full.
+const PWM_FREQUENCY_HZ: f64 = 50.0;
+
+/// Drives the fan at variable speed.
+///
+/// Software PWM stops when the pin is dropped, so the pin is kept for as long as the fan runs
+/// at a partial speed. Full speed and off are set like `turn_fan_full_on` and `turn_fan_off`.
+#[derive(Default)]
+pub struct Fan {
+    pwm_pin: Option<OutputPin>,
+}
+
+impl Fan {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the duty cycle from 0.0 (off) to 1.0 (full speed).
+    pub fn set_duty(&mut self, duty: f32) -> rppal::gpio::Result<()> {
+        let duty = duty.clamp(0.0, 1.0);
+        if duty > 0.0 && duty < 1.0 {
+            if self.pwm_pin.is_none() {
+                self.pwm_pin = Some(fan_pin()?);
+            }
+            if let Some(pin) = &mut self.pwm_pin {
+                pin.set_pwm_frequency(PWM_FREQUENCY_HZ, f64::from(duty))?;
+            }
+            return Ok(());
+        }
+
+        self.release();
+        if duty > 0.0 {
+            try_turn_fan_full_on()
+        } else {
+            try_turn_fan_off()
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.pwm_pin.is_some() || fan_is_enabled()
+    }
+
+    /// Stops the software PWM, so other functions in this module can use the pin again.
+    pub fn release(&mut self) {
+        if let Some(mut pin) = self.pwm_pin.take() {
+            let _ = pin.clear_pwm();
+        }
+    }
+}
+
+/// How the fan is brought to a partial speed, so it does not stall.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpinUp {
+    /// Duty cycles below this turn the fan off instead.
+    pub min_duty: f32,
+    /// Time at full speed before dropping to a partial duty cycle from standstill.
+    pub kick_start: Duration,
+}
+
+impl SpinUp {
+    pub fn new(config: &FanshimConfig) -> Self {
+        Self {
+            min_duty: config.min_fan_speed,
+            kick_start: config.kick_start_time,
+        }
+    }
+
+    /// The duty cycle the fan actually runs at when `duty` is asked for.
+    pub fn duty(&self, duty: f32) -> f32 {
+        if duty < self.min_duty {
+            0.0
+        } else {
+            duty.clamp(0.0, 1.0)
+        }
+    }
+
+    /// How long to run at full speed first when going from off to `duty`, if at all.
+    pub fn kick(&self, was_enabled: bool, duty: f32) -> Option<Duration> {
+        let duty = self.duty(duty);
+        if was_enabled || duty <= 0.0 || duty >= 1.0 || self.kick_start.as_nanos() == 0 {
+            None
+        } else {
+            Some(self.kick_start)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SpinUp;
+    use std::time::Duration;
+
+    #[test]
+    fn kicks_from_standstill_to_partial_speeds_only() {
+        let spin_up = SpinUp {
+            min_duty: 0.3,
+            kick_start: Duration::from_millis(500),
+        };
+
+        assert_eq!(spin_up.duty(0.2), 0.0);
+        assert_eq!(spin_up.duty(0.3), 0.3);
+        assert_eq!(spin_up.duty(1.5), 1.0);
+
+        assert_eq!(spin_up.kick(false, 0.4), Some(Duration::from_millis(500)));
+        assert_eq!(spin_up.kick(true, 0.4), None);
+        assert_eq!(spin_up.kick(false, 1.0), None);
+        assert_eq!(spin_up.kick(false, 0.2), None);
+    }
 }
@@ -0,0 +1,268 @@
+use crate::{MilliCelsius, MILLI_CELSIUS_IN_CELSIUS};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Which filter smooths the temperature readings before `main_loop` acts on them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterConfig {
+    /// Readings are used as they are.
+    None,
+    /// Mean of the last `window` readings.
+    MovingAverage { window: usize },
+    /// Each reading is weighted by `alpha` against the previous output.
+    ExponentialMovingAverage { alpha: f32 },
+    /// Median of the last `window` readings. Ignores single spikes entirely.
+    Median { window: usize },
+    /// Follows the readings, but moves at most `degrees_per_second` between readings.
+    RateLimit { degrees_per_second: f32 },
+}
+
+/// A stage between the temperature sensor and the fan control logic.
+pub trait TemperatureFilter: Send {
+    /// Takes the next raw reading and returns the filtered temperature.
+    fn filter(&mut self, reading: MilliCelsius) -> MilliCelsius;
+}
+
+/// The filter for `config`, taking readings `delay_between_readings` apart.
+pub fn from_config(
+    config: FilterConfig,
+    delay_between_readings: Duration,
+) -> Box<dyn TemperatureFilter> {
+    match config {
+        FilterConfig::None => Box::new(Passthrough),
+        FilterConfig::MovingAverage { window } => Box::new(MovingAverage::new(window)),
+        FilterConfig::ExponentialMovingAverage { alpha } => {
+            Box::new(ExponentialMovingAverage::new(alpha))
+        }
+        FilterConfig::Median { window } => Box::new(Median::new(window)),
+        FilterConfig::RateLimit { degrees_per_second } => Box::new(RateLimit::new(max_change(
+            degrees_per_second,
+            delay_between_readings,
+        ))),
+    }
+}
+
+pub struct Passthrough;
+
+impl TemperatureFilter for Passthrough {
+    fn filter(&mut self, reading: MilliCelsius) -> MilliCelsius {
+        reading
+    }
+}
+
+/// Keeps the last `window` readings.
+struct Window {
+    size: usize,
+    readings: VecDeque<i32>,
+}
+
+impl Window {
+    fn new(size: usize) -> Self {
+        let size = size.max(1);
+        Self {
+            size,
+            readings: VecDeque::with_capacity(size),
+        }
+    }
+
+    fn push(&mut self, reading: MilliCelsius) {
+        if self.readings.len() == self.size {
+            self.readings.pop_front();
+        }
+        self.readings.push_back(reading.0);
+    }
+}
+
+pub struct MovingAverage {
+    window: Window,
+}
+
+impl MovingAverage {
+    pub fn new(window: usize) -> Self {
+        Self {
+            window: Window::new(window),
+        }
+    }
+}
+
+impl TemperatureFilter for MovingAverage {
+    fn filter(&mut self, reading: MilliCelsius) -> MilliCelsius {
+        self.window.push(reading);
+        let sum: i64 = self.window.readings.iter().map(|&r| i64::from(r)).sum();
+        MilliCelsius((sum / self.window.readings.len() as i64) as i32)
+    }
+}
+
+pub struct ExponentialMovingAverage {
+    alpha: f64,
+    value: Option<f64>,
+}
+
+impl ExponentialMovingAverage {
+    pub fn new(alpha: f32) -> Self {
+        Self {
+            alpha: f64::from(alpha).clamp(0.0, 1.0),
+            value: None,
+        }
+    }
+}
+
+impl TemperatureFilter for ExponentialMovingAverage {
+    fn filter(&mut self, reading: MilliCelsius) -> MilliCelsius {
+        let reading = f64::from(reading.0);
+        let value = match self.value {
+            Some(previous) => previous + self.alpha * (reading - previous),
+            None => reading,
+        };
+        self.value = Some(value);
+        MilliCelsius(value.round() as i32)
+    }
+}
+
+pub struct Median {
+    window: Window,
+}
+
+impl Median {
+    pub fn new(window: usize) -> Self {
+        Self {
+            window: Window::new(window),
+        }
+    }
+}
+
+impl TemperatureFilter for Median {
+    fn filter(&mut self, reading: MilliCelsius) -> MilliCelsius {
+        self.window.push(reading);
+        let mut sorted: Vec<i32> = self.window.readings.iter().copied().collect();
+        sorted.sort_unstable();
+        let middle = sorted.len() / 2;
+        if sorted.len() % 2 == 0 {
+            MilliCelsius(((i64::from(sorted[middle - 1]) + i64::from(sorted[middle])) / 2) as i32)
+        } else {
+            MilliCelsius(sorted[middle])
+        }
+    }
+}
+
+pub struct RateLimit {
+    max_change: i32,
+    value: Option<i32>,
+}
+
+impl RateLimit {
+    pub fn new(max_change: MilliCelsius) -> Self {
+        Self {
+            max_change: max_change.0.abs(),
+            value: None,
+        }
+    }
+}
+
+/// The most the output of `RateLimit` moves per reading. At least a millidegree, as it would
+/// never follow the readings otherwise.
+fn max_change(degrees_per_second: f32, delay_between_readings: Duration) -> MilliCelsius {
+    let millidegrees = degrees_per_second.abs()
+        * delay_between_readings.as_secs_f32()
+        * MILLI_CELSIUS_IN_CELSIUS as f32;
+    MilliCelsius((millidegrees.round() as i32).max(1))
+}
+
+impl TemperatureFilter for RateLimit {
+    fn filter(&mut self, reading: MilliCelsius) -> MilliCelsius {
+        let value = match self.value {
+            Some(previous) => {
+                previous + (reading.0 - previous).clamp(-self.max_change, self.max_change)
+            }
+            None => reading.0,
+        };
+        self.value = Some(value);
+        MilliCelsius(value)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{from_config, max_change, FilterConfig};
+    use crate::MilliCelsius;
+    use std::time::Duration;
+
+    fn run(config: FilterConfig, readings: &[i32]) -> Vec<i32> {
+        let mut f = from_config(config, Duration::from_secs(1));
+        readings
+            .iter()
+            .map(|&r| f.filter(MilliCelsius(r)).0)
+            .collect()
+    }
+
+    #[test]
+    fn passthrough_returns_readings() {
+        assert_eq!(
+            run(FilterConfig::None, &[50_000, 70_000, 40_000]),
+            vec![50_000, 70_000, 40_000]
+        );
+    }
+
+    #[test]
+    fn moving_average_averages_window() {
+        assert_eq!(
+            run(
+                FilterConfig::MovingAverage { window: 3 },
+                &[30_000, 60_000, 60_000, 90_000]
+            ),
+            vec![30_000, 45_000, 50_000, 70_000]
+        );
+    }
+
+    #[test]
+    fn exponential_moving_average_weights_newest_reading() {
+        assert_eq!(
+            run(
+                FilterConfig::ExponentialMovingAverage { alpha: 0.5 },
+                &[40_000, 60_000, 60_000, 40_000]
+            ),
+            vec![40_000, 50_000, 55_000, 47_500]
+        );
+    }
+
+    #[test]
+    fn median_ignores_single_spikes() {
+        assert_eq!(
+            run(
+                FilterConfig::Median { window: 3 },
+                &[50_000, 50_000, 90_000, 50_000, 20_000, 50_000]
+            ),
+            vec![50_000, 50_000, 50_000, 50_000, 50_000, 50_000]
+        );
+    }
+
+    #[test]
+    fn rate_limit_caps_change_per_reading() {
+        assert_eq!(
+            run(
+                FilterConfig::RateLimit {
+                    degrees_per_second: 2.0
+                },
+                &[50_000, 60_000, 60_000, 51_000, 40_000]
+            ),
+            vec![50_000, 52_000, 54_000, 52_000, 50_000]
+        );
+    }
+
+    #[test]
+    fn rate_limit_always_follows_small_rates() {
+        let short = Duration::from_millis(200);
+        assert_eq!(max_change(0.001, short), MilliCelsius(1));
+        assert_eq!(max_change(0.0, short), MilliCelsius(1));
+        assert_eq!(max_change(0.0075, short), MilliCelsius(2));
+
+        let mut f = from_config(
+            FilterConfig::RateLimit {
+                degrees_per_second: 0.001,
+            },
+            short,
+        );
+        f.filter(MilliCelsius(50_000));
+        assert_eq!(f.filter(MilliCelsius(70_000)), MilliCelsius(50_001));
+    }
+}
@@ -0,0 +1,99 @@
+use crate::config::FanshimConfig;
+use log::info;
+use std::collections::VecDeque;
+use std::time::{Duration, SystemTime};
+
+const HOUR: Duration = Duration::from_secs(60 * 60);
+
+/// Holds back fan changes that would cycle the fan too often.
+///
+/// Enforces the minimum time the fan stays on or off after a change, and the maximum number
+/// of changes within the last hour.
+pub struct FanGuard {
+    min_on_time: Duration,
+    min_off_time: Duration,
+    max_changes_per_hour: usize,
+    last_change: Option<SystemTime>,
+    /// Times of the changes within the last hour, oldest first.
+    changes: VecDeque<SystemTime>,
+    /// Whether a suppressed change was logged already, so it is logged once rather than every reading.
+    suppressing: bool,
+}
+
+impl FanGuard {
+    pub fn new(config: &FanshimConfig) -> Self {
+        Self {
+            min_on_time: config.min_on_time,
+            min_off_time: config.min_off_time,
+            max_changes_per_hour: config.max_changes_per_hour as usize,
+            last_change: None,
+            changes: VecDeque::new(),
+            suppressing: false,
+        }
+    }
+
+    /// Takes the limits of `config`, keeping the changes made so far.
+    pub fn reconfigure(&mut self, config: &FanshimConfig) {
+        self.min_on_time = config.min_on_time;
+        self.min_off_time = config.min_off_time;
+        self.max_changes_per_hour = config.max_changes_per_hour as usize;
+    }
+
+    /// Whether the fan may be turned on (`turn_on`) or off at `now`.
+    pub fn allows(&mut self, now: SystemTime, turn_on: bool) -> bool {
+        while self.changes.front().is_some_and(|&t| since(now, t) >= HOUR) {
+            self.changes.pop_front();
+        }
+
+        let reason = match self.last_change {
+            Some(last) if turn_on && since(now, last) < self.min_off_time => Some(format!(
+                "it has been off for less than the minimum of {}s",
+                self.min_off_time.as_secs()
+            )),
+            Some(last) if !turn_on && since(now, last) < self.min_on_time => Some(format!(
+                "it has been on for less than the minimum of {}s",
+                self.min_on_time.as_secs()
+            )),
+            _ if self.max_changes_per_hour > 0
+                && self.changes.len() >= self.max_changes_per_hour =>
+            {
+                Some(format!(
+                    "it was turned on or off {} times within the last hour",
+                    self.changes.len()
+                ))
+            }
+            _ => None,
+        };
+
+        match reason {
+            Some(reason) => {
+                if !self.suppressing {
+                    info!(
+                        "Not turning fan {} yet, {}",
+                        if turn_on { "on" } else { "off" },
+                        reason
+                    );
+                    self.suppressing = true;
+                }
+                false
+            }
+            None => {
+                self.suppressing = false;
+                true
+            }
+        }
+    }
+
+    /// Records that the fan was turned on or off at `now`.
+    pub fn record(&mut self, now: SystemTime) {
+        self.last_change = Some(now);
+        self.changes.push_back(now);
+        self.suppressing = false;
+    }
+}
+
+/// Time from `earlier` to `now`. Unlimited if the clock went backwards, so a clock change
+/// never blocks the fan.
+fn since(now: SystemTime, earlier: SystemTime) -> Duration {
+    now.duration_since(earlier).unwrap_or(Duration::MAX)
+}
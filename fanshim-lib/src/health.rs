@@ -0,0 +1,204 @@
+use crate::config::FanshimConfig;
+use crate::MilliCelsius;
+use std::collections::VecDeque;
+use std::time::{Duration, SystemTime};
+
+/// What the health monitor concludes about the fan.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Health {
+    Ok,
+    /// The fan ran at full speed, but the temperature rose about as fast as with the fan off.
+    FanSuspectedFailed,
+}
+
+/// Judges whether the fan still cools, from the readings `main_loop` feeds it.
+///
+/// The heating rate over the last minutes the fan was off is kept as a baseline. Once the fan
+/// has run at full speed for `minutes_at_full_speed`, the rate over that time is compared to it.
+pub struct HealthMonitor {
+    window: Duration,
+    rise_ratio: f64,
+    /// Readings of the current period within the last `window`.
+    readings: VecDeque<(SystemTime, MilliCelsius)>,
+    /// Whether the current period is at full speed (`Some(true)`), off (`Some(false)`) or at
+    /// a partial speed, which is not judged.
+    at_full_speed: Option<bool>,
+    period_start: Option<SystemTime>,
+    /// Millidegrees per second at the end of the last period with the fan off.
+    off_rate: Option<f64>,
+    health: Health,
+}
+
+impl HealthMonitor {
+    pub fn new(config: &FanshimConfig) -> Self {
+        Self {
+            window: Duration::from_secs(config.health.minutes_at_full_speed * 60),
+            rise_ratio: config.health.rise_ratio,
+            readings: VecDeque::new(),
+            at_full_speed: None,
+            period_start: None,
+            off_rate: None,
+            health: Health::Ok,
+        }
+    }
+
+    /// Takes the settings of `config`, keeping the readings and the baseline.
+    pub fn reconfigure(&mut self, config: &FanshimConfig) {
+        self.window = Duration::from_secs(config.health.minutes_at_full_speed * 60);
+        self.rise_ratio = config.health.rise_ratio;
+    }
+
+    pub fn health(&self) -> Health {
+        self.health
+    }
+
+    /// Takes a reading taken with the fan at `duty`. Returns the health if it changed.
+    pub fn update(
+        &mut self,
+        now: SystemTime,
+        temperature: MilliCelsius,
+        duty: f32,
+    ) -> Option<Health> {
+        if self.window.as_secs() == 0 {
+            return None;
+        }
+
+        let at_full_speed = if duty >= 1.0 {
+            Some(true)
+        } else if duty <= 0.0 {
+            Some(false)
+        } else {
+            None
+        };
+        if at_full_speed != self.at_full_speed || self.period_start.is_none() {
+            if self.at_full_speed == Some(false) {
+                self.off_rate = rate(&self.readings).or(self.off_rate);
+            }
+            self.readings.clear();
+            self.at_full_speed = at_full_speed;
+            self.period_start = Some(now);
+        }
+        if at_full_speed.is_some() {
+            self.readings.push_back((now, temperature));
+            while self
+                .readings
+                .front()
+                .is_some_and(|&(t, _)| since(now, t) > self.window)
+            {
+                self.readings.pop_front();
+            }
+        }
+
+        let long_at_full_speed = at_full_speed == Some(true)
+            && self
+                .period_start
+                .is_some_and(|start| since(now, start) >= self.window);
+        let health = match (self.off_rate, rate(&self.readings)) {
+            (Some(off_rate), Some(on_rate))
+                if long_at_full_speed
+                    && off_rate > 0.0
+                    && on_rate > 0.0
+                    && on_rate >= off_rate * self.rise_ratio =>
+            {
+                Health::FanSuspectedFailed
+            }
+            _ => Health::Ok,
+        };
+
+        if health == self.health {
+            None
+        } else {
+            self.health = health;
+            Some(health)
+        }
+    }
+}
+
+/// Least squares slope of the readings in millidegrees per second.
+fn rate(readings: &VecDeque<(SystemTime, MilliCelsius)>) -> Option<f64> {
+    let (first, _) = *readings.front()?;
+    let points: Vec<(f64, f64)> = readings
+        .iter()
+        .map(|&(t, c)| (since(t, first).as_secs_f64(), f64::from(c.0)))
+        .collect();
+    let n = points.len() as f64;
+    let mean_t = points.iter().map(|p| p.0).sum::<f64>() / n;
+    let mean_c = points.iter().map(|p| p.1).sum::<f64>() / n;
+    let covariance: f64 = points.iter().map(|p| (p.0 - mean_t) * (p.1 - mean_c)).sum();
+    let variance: f64 = points.iter().map(|p| (p.0 - mean_t).powi(2)).sum();
+    if variance > 0.0 {
+        Some(covariance / variance)
+    } else {
+        None
+    }
+}
+
+fn since(now: SystemTime, earlier: SystemTime) -> Duration {
+    now.duration_since(earlier).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Health, HealthMonitor};
+    use crate::config::FanshimConfig;
+    use crate::MilliCelsius;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    /// Feeds a reading every 10 seconds for `minutes`, changing by `rate` millidegrees per
+    /// second. Returns every change of health with the minute it happened at.
+    fn feed(
+        monitor: &mut HealthMonitor,
+        clock: &mut SystemTime,
+        temperature: &mut f64,
+        minutes: u64,
+        rate: f64,
+        duty: f32,
+    ) -> Vec<(u64, Health)> {
+        let mut changes = Vec::new();
+        for i in 0..minutes * 6 {
+            *clock += Duration::from_secs(10);
+            *temperature += rate * 10.0;
+            // Sensor noise of a degree.
+            let noise = if i % 2 == 0 { 500 } else { -500 };
+            let reading = MilliCelsius(*temperature as i32 + noise);
+            if let Some(health) = monitor.update(*clock, reading, duty) {
+                changes.push((i / 6, health));
+            }
+        }
+        changes
+    }
+
+    #[test]
+    fn suspects_failure_when_fan_does_not_slow_heating() {
+        let mut monitor = HealthMonitor::new(&FanshimConfig::default());
+        let mut clock = UNIX_EPOCH;
+        let mut temperature = 50_000.0;
+
+        assert!(feed(&mut monitor, &mut clock, &mut temperature, 5, 20.0, 0.0).is_empty());
+        // Heats up just as fast at full speed, flagged after ten minutes.
+        assert_eq!(
+            feed(&mut monitor, &mut clock, &mut temperature, 12, 20.0, 1.0),
+            vec![(10, Health::FanSuspectedFailed)]
+        );
+        // Turning the fan off ends the suspicion, there is nothing to judge.
+        assert_eq!(
+            feed(&mut monitor, &mut clock, &mut temperature, 1, 20.0, 0.0),
+            vec![(0, Health::Ok)]
+        );
+    }
+
+    #[test]
+    fn working_fan_is_not_suspected() {
+        let mut monitor = HealthMonitor::new(&FanshimConfig::default());
+        let mut clock = UNIX_EPOCH;
+        let mut temperature = 50_000.0;
+
+        feed(&mut monitor, &mut clock, &mut temperature, 5, 20.0, 0.0);
+        // Cools down, then holds a steady temperature under load.
+        assert!(feed(&mut monitor, &mut clock, &mut temperature, 5, -10.0, 1.0).is_empty());
+        assert!(feed(&mut monitor, &mut clock, &mut temperature, 30, 0.0, 1.0).is_empty());
+        // Still warming slowly, at a fraction of the rate with the fan off.
+        assert!(feed(&mut monitor, &mut clock, &mut temperature, 30, 5.0, 1.0).is_empty());
+        assert_eq!(monitor.health(), Health::Ok);
+    }
+}
@@ -0,0 +1,323 @@
+use crate::MilliCelsius;
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const CSV_HEADER: &str = "timestamp,temperature,duty,event";
+
+#[derive(serde::Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Event {
+    Reading,
+    FanOn,
+    FanOff,
+}
+
+impl Event {
+    fn as_str(self) -> &'static str {
+        match self {
+            Event::Reading => "reading",
+            Event::FanOn => "fan_on",
+            Event::FanOff => "fan_off",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "reading" => Some(Event::Reading),
+            "fan_on" => Some(Event::FanOn),
+            "fan_off" => Some(Event::FanOff),
+            _ => None,
+        }
+    }
+}
+
+#[derive(serde::Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct Entry {
+    /// Seconds since the unix epoch.
+    pub timestamp: u64,
+    pub temperature: MilliCelsius,
+    /// Fan duty cycle from 0.0 (off) to 1.0 (full speed).
+    pub duty: f32,
+    pub event: Event,
+}
+
+impl Entry {
+    fn to_csv(self) -> String {
+        format!(
+            "{},{},{},{}",
+            self.timestamp,
+            self.temperature.0,
+            self.duty,
+            self.event.as_str()
+        )
+    }
+
+    fn from_csv(line: &str) -> Option<Self> {
+        let mut fields = line.trim().split(',');
+        let entry = Self {
+            timestamp: fields.next()?.parse().ok()?,
+            temperature: MilliCelsius(fields.next()?.parse().ok()?),
+            duty: fields.next()?.parse().ok()?,
+            event: Event::parse(fields.next()?)?,
+        };
+        match fields.next() {
+            Some(_) => None,
+            None => Some(entry),
+        }
+    }
+}
+
+/// The most recent readings and fan actions, optionally mirrored to a CSV file.
+///
+/// The file is appended to on every entry and rewritten from memory once it holds twice
+/// `capacity` entries, so it never grows unbounded.
+#[derive(Debug)]
+pub struct History {
+    entries: VecDeque<Entry>,
+    capacity: usize,
+    file: Option<HistoryFile>,
+}
+
+#[derive(Debug)]
+struct HistoryFile {
+    path: PathBuf,
+    file: File,
+    lines: usize,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+            file: None,
+        }
+    }
+
+    /// Loads the entries already in `path` and appends new ones to it.
+    pub fn with_file(capacity: usize, path: &Path) -> io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut history = Self::new(capacity);
+        let existing = match read_file(path) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        for entry in existing {
+            history.push(entry);
+        }
+
+        history.file = Some(HistoryFile {
+            path: path.to_owned(),
+            file: OpenOptions::new().create(true).append(true).open(path)?,
+            lines: 0,
+        });
+        // Rewriting straight away trims the file if the capacity was lowered, and adds the header.
+        history.rewrite_file()?;
+        Ok(history)
+    }
+
+    pub fn record(&mut self, entry: Entry) -> io::Result<()> {
+        self.push(entry);
+
+        let needs_rewrite = match &mut self.file {
+            Some(f) => {
+                writeln!(f.file, "{}", entry.to_csv())?;
+                f.lines += 1;
+                f.lines > self.capacity * 2
+            }
+            None => false,
+        };
+        if needs_rewrite {
+            self.rewrite_file()?;
+        }
+        Ok(())
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &Entry> {
+        self.entries.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn push(&mut self, entry: Entry) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    fn rewrite_file(&mut self) -> io::Result<()> {
+        let f = match &mut self.file {
+            Some(f) => f,
+            None => return Ok(()),
+        };
+
+        let mut tmp_path = f.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let mut tmp = File::create(&tmp_path)?;
+        write_csv(&mut tmp, self.entries.iter())?;
+        tmp.sync_all()?;
+        fs::rename(&tmp_path, &f.path)?;
+
+        f.file = OpenOptions::new().append(true).open(&f.path)?;
+        f.lines = self.entries.len();
+        Ok(())
+    }
+}
+
+/// Reads a history file written by `History::with_file`, skipping malformed lines.
+pub fn read_file(path: &Path) -> io::Result<Vec<Entry>> {
+    let f = BufReader::new(File::open(path)?);
+    let mut entries = Vec::new();
+    for line in f.lines() {
+        if let Some(entry) = Entry::from_csv(&line?) {
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+pub fn write_csv<'a, W, I>(w: &mut W, entries: I) -> io::Result<()>
+where
+    W: Write,
+    I: IntoIterator<Item = &'a Entry>,
+{
+    writeln!(w, "{}", CSV_HEADER)?;
+    for entry in entries {
+        writeln!(w, "{}", entry.to_csv())?;
+    }
+    Ok(())
+}
+
+pub fn write_json<'a, W, I>(w: &mut W, entries: I) -> io::Result<()>
+where
+    W: Write,
+    I: IntoIterator<Item = &'a Entry>,
+{
+    let entries: Vec<&Entry> = entries.into_iter().collect();
+    serde_json::to_writer_pretty(&mut *w, &entries)?;
+    writeln!(w)
+}
+
+/// Parses durations like "90s", "30m", "1h" or "2d".
+pub fn parse_duration(s: &str) -> Option<Duration> {
+    let s = s.trim();
+    let (number, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit())?);
+    let number: u64 = number.parse().ok()?;
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return None,
+    };
+    Some(Duration::from_secs(number.checked_mul(seconds)?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_duration, read_file, write_csv, write_json, Entry, Event, History};
+    use crate::MilliCelsius;
+    use std::time::Duration;
+
+    fn entry(timestamp: u64) -> Entry {
+        Entry {
+            timestamp,
+            temperature: MilliCelsius(50_000 + timestamp as i32),
+            duty: 0.0,
+            event: Event::Reading,
+        }
+    }
+
+    #[test]
+    fn keeps_most_recent_entries() {
+        let mut h = History::new(3);
+        for t in 0..5 {
+            h.record(entry(t)).unwrap();
+        }
+
+        let timestamps: Vec<u64> = h.entries().map(|e| e.timestamp).collect();
+        assert_eq!(timestamps, vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn persisted_file_is_reloaded_and_bounded() {
+        let dir = std::env::temp_dir().join(format!("fanshim-history-{}", std::process::id()));
+        let path = dir.join("history.csv");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        {
+            let mut h = History::with_file(3, &path).unwrap();
+            for t in 0..10 {
+                h.record(entry(t)).unwrap();
+            }
+            assert!(read_file(&path).unwrap().len() <= 6);
+        }
+
+        let h = History::with_file(3, &path).unwrap();
+        let timestamps: Vec<u64> = h.entries().map(|e| e.timestamp).collect();
+        assert_eq!(timestamps, vec![7, 8, 9]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn exports_csv_and_json() {
+        let entries = vec![
+            entry(1),
+            Entry {
+                timestamp: 2,
+                temperature: MilliCelsius(66_000),
+                duty: 1.0,
+                event: Event::FanOn,
+            },
+        ];
+
+        let mut csv = Vec::new();
+        write_csv(&mut csv, &entries).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "timestamp,temperature,duty,event\n1,50001,0,reading\n2,66000,1,fan_on\n"
+        );
+
+        let mut json = Vec::new();
+        write_json(&mut json, &entries[1..]).unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!([
+                {"timestamp": 2, "temperature": 66000, "duty": 1.0, "event": "fan_on"}
+            ])
+        );
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("90s"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_duration("30m"), Some(Duration::from_secs(30 * 60)));
+        assert_eq!(parse_duration("1h"), Some(Duration::from_secs(60 * 60)));
+        assert_eq!(
+            parse_duration("2d"),
+            Some(Duration::from_secs(2 * 24 * 60 * 60))
+        );
+        assert_eq!(parse_duration("1"), None);
+        assert_eq!(parse_duration("h"), None);
+        assert_eq!(parse_duration("1w"), None);
+    }
+}
@@ -0,0 +1,296 @@
+use crate::config;
+use crate::MilliCelsius;
+use log::{debug, error, info, warn};
+use std::fmt;
+use std::io::{self, Read};
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A state change a hook can be configured for.
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Event {
+    FanOn,
+    FanOff,
+    Alarm,
+    AlarmClear,
+    ButtonLongPress,
+}
+
+impl Event {
+    /// The state passed to the hook as `FANSHIM_STATE`.
+    pub fn state(self) -> &'static str {
+        match self {
+            Event::FanOn => "fan_on",
+            Event::FanOff => "fan_off",
+            Event::Alarm => "alarm",
+            Event::AlarmClear => "alarm_clear",
+            Event::ButtonLongPress => "button_long_press",
+        }
+    }
+}
+
+impl fmt::Display for Event {
+    /// The key of the hook in the `[hooks]` section.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "on_{}", self.state())
+    }
+}
+
+/// An event, with what the hook is told about the driver when it happened.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trigger {
+    pub event: Event,
+    pub temperature: MilliCelsius,
+    /// Whether the controller has the fan on, including while it ramps up.
+    pub fan_enabled: bool,
+    pub profile: Option<String>,
+    /// What is wrong, e.g. "network down", empty unless an alarm is active.
+    pub alarms: Vec<String>,
+}
+
+impl Trigger {
+    pub fn environment(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("FANSHIM_TEMP_MC", self.temperature.0.to_string()),
+            ("FANSHIM_STATE", self.event.state().to_owned()),
+            ("FANSHIM_PROFILE", self.profile.clone().unwrap_or_default()),
+            ("FANSHIM_ALARMS", self.alarms.join(", ")),
+        ]
+    }
+}
+
+/// A hook command to run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Invocation {
+    pub event: Event,
+    pub command: String,
+    pub environment: Vec<(&'static str, String)>,
+    /// `None` lets the command run as long as it takes.
+    pub timeout: Option<Duration>,
+}
+
+/// Runs hook commands. Must not wait for them, as it is called from the driver loop.
+pub trait Executor {
+    fn execute(&mut self, invocation: Invocation);
+}
+
+/// Runs each command with `sh -c` on a thread of its own, logging how it ended.
+#[derive(Debug, Default)]
+pub struct CommandExecutor;
+
+impl Executor for CommandExecutor {
+    fn execute(&mut self, invocation: Invocation) {
+        thread::spawn(move || match run(&invocation) {
+            Ok(Outcome::Exited {
+                code: Some(0),
+                stderr,
+            }) => {
+                if stderr.trim().is_empty() {
+                    debug!("Hook {} finished", invocation.event);
+                } else {
+                    info!("Hook {} finished: {}", invocation.event, stderr.trim());
+                }
+            }
+            Ok(Outcome::Exited {
+                code: Some(code),
+                stderr,
+            }) => warn!(
+                "Hook {} exited with {}: {}",
+                invocation.event,
+                code,
+                stderr.trim()
+            ),
+            Ok(Outcome::Exited { code: None, stderr }) => warn!(
+                "Hook {} was killed by a signal: {}",
+                invocation.event,
+                stderr.trim()
+            ),
+            Ok(Outcome::TimedOut { stderr }) => warn!(
+                "Hook {} did not finish within {:?} and was killed: {}",
+                invocation.event,
+                invocation.timeout.unwrap_or_default(),
+                stderr.trim()
+            ),
+            Err(e) => error!("Unable to run hook {}: {}", invocation.event, e),
+        });
+    }
+}
+
+/// How a hook command ended.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    /// `code` is `None` if the command was killed by a signal.
+    Exited {
+        code: Option<i32>,
+        stderr: String,
+    },
+    TimedOut {
+        stderr: String,
+    },
+}
+
+/// Runs the command and waits for it, killing it and everything it started on timeout.
+pub fn run(invocation: &Invocation) -> io::Result<Outcome> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(&invocation.command)
+        .envs(invocation.environment.iter().cloned())
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        // A group of its own, so commands it started are killed with it.
+        .process_group(0)
+        .spawn()?;
+
+    // Read while waiting, so a chatty command does not block on a full pipe.
+    let mut pipe = child.stderr.take();
+    let reader = thread::spawn(move || {
+        let mut stderr = String::new();
+        if let Some(pipe) = &mut pipe {
+            let _ = pipe.read_to_string(&mut stderr);
+        }
+        stderr
+    });
+
+    let started = Instant::now();
+    let code = loop {
+        if let Some(status) = child.try_wait()? {
+            break Some(status.code());
+        }
+        if invocation.timeout.is_some_and(|t| started.elapsed() >= t) {
+            // Safe because kill only sends a signal.
+            unsafe {
+                libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+            }
+            child.wait()?;
+            break None;
+        }
+        thread::sleep(POLL_INTERVAL);
+    };
+
+    let stderr = reader.join().unwrap_or_default();
+    Ok(match code {
+        Some(code) => Outcome::Exited { code, stderr },
+        None => Outcome::TimedOut { stderr },
+    })
+}
+
+/// Runs the commands of the `[hooks]` section.
+pub struct Hooks<E> {
+    config: config::Hooks,
+    executor: E,
+}
+
+impl<E: Executor> Hooks<E> {
+    pub fn new(executor: E) -> Self {
+        Self {
+            config: config::Hooks::default(),
+            executor,
+        }
+    }
+
+    pub fn set_config(&mut self, config: &config::Hooks) {
+        self.config = config.clone();
+    }
+
+    /// Runs the command configured for the event of `trigger`, if any.
+    pub fn fire(&mut self, trigger: &Trigger) {
+        if let Some(command) = self.config.command(trigger.event) {
+            let timeout = self.config.timeout_seconds;
+            self.executor.execute(Invocation {
+                event: trigger.event,
+                command: command.to_owned(),
+                environment: trigger.environment(),
+                timeout: Some(Duration::from_secs(timeout)).filter(|_| timeout > 0),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{run, Event, Executor, Hooks, Invocation, Outcome, Trigger};
+    use crate::config;
+    use crate::MilliCelsius;
+    use std::time::{Duration, Instant};
+
+    #[derive(Default)]
+    struct FakeExecutor {
+        executed: Vec<Invocation>,
+    }
+
+    impl Executor for FakeExecutor {
+        fn execute(&mut self, invocation: Invocation) {
+            self.executed.push(invocation);
+        }
+    }
+
+    fn trigger(event: Event) -> Trigger {
+        Trigger {
+            event,
+            temperature: MilliCelsius(61_500),
+            fan_enabled: true,
+            profile: Some("silent".to_owned()),
+            alarms: vec!["under-voltage".to_owned()],
+        }
+    }
+
+    #[test]
+    fn fires_configured_hooks_with_environment() {
+        let mut hooks = Hooks::new(FakeExecutor::default());
+        hooks.set_config(&config::Hooks {
+            on_fan_on: Some("logger fan on".to_owned()),
+            ..config::Hooks::default()
+        });
+
+        hooks.fire(&trigger(Event::FanOff));
+        hooks.fire(&trigger(Event::FanOn));
+
+        assert_eq!(
+            hooks.executor.executed,
+            vec![Invocation {
+                event: Event::FanOn,
+                command: "logger fan on".to_owned(),
+                environment: vec![
+                    ("FANSHIM_TEMP_MC", "61500".to_owned()),
+                    ("FANSHIM_STATE", "fan_on".to_owned()),
+                    ("FANSHIM_PROFILE", "silent".to_owned()),
+                    ("FANSHIM_ALARMS", "under-voltage".to_owned()),
+                ],
+                timeout: Some(Duration::from_secs(10)),
+            }]
+        );
+    }
+
+    #[test]
+    fn runs_commands_and_kills_them_on_timeout() {
+        let invocation = |command: &str| Invocation {
+            event: Event::Alarm,
+            command: command.to_owned(),
+            environment: trigger(Event::Alarm).environment(),
+            timeout: Some(Duration::from_millis(200)),
+        };
+
+        assert_eq!(
+            run(&invocation("echo $FANSHIM_STATE >&2; exit 3")).unwrap(),
+            Outcome::Exited {
+                code: Some(3),
+                stderr: "alarm\n".to_owned()
+            }
+        );
+
+        let started = Instant::now();
+        assert_eq!(
+            run(&invocation("sleep 10; true")).unwrap(),
+            Outcome::TimedOut {
+                stderr: String::new()
+            }
+        );
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
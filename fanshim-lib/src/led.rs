@@ -1,21 +1,279 @@
+use crate::color::Color;
 use blinkt::Blinkt;
 
 const LED_DATA_PIN: u8 = 15;
 const LED_CLOCK_PIN: u8 = 14;
-const LED_NUMBER_OF_PIXELS: usize = 8;
+/// The Fan SHIM has a single APA102.
+pub const FAN_SHIM_PIXELS: usize = 1;
+/// Highest level of the APA102's 5 bit global brightness.
+const MAX_BRIGHTNESS_LEVEL: f32 = 31.0;
 
-pub fn set_led_rgb(red: u8, green: u8, blue: u8) {
-    let mut led = Blinkt::with_settings(LED_DATA_PIN, LED_CLOCK_PIN, LED_NUMBER_OF_PIXELS).unwrap();
-    led.set_clear_on_drop(false);
+/// How a brightness from 0.0 to 1.0 maps to light output.
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum BrightnessCurve {
+    /// Light output proportional to the brightness.
+    #[default]
+    Linear,
+    /// Equal steps in brightness look like equal steps in lightness (CIE 1931).
+    Perceptual,
+}
+
+impl BrightnessCurve {
+    /// Light output from 0.0 to 1.0.
+    pub fn intensity(self, brightness: f32) -> f32 {
+        let brightness = brightness.clamp(0.0, 1.0);
+        match self {
+            BrightnessCurve::Linear => brightness,
+            BrightnessCurve::Perceptual => {
+                let lightness = brightness * 100.0;
+                if lightness <= 8.0 {
+                    lightness / 903.3
+                } else {
+                    ((lightness + 16.0) / 116.0).powi(3)
+                }
+            }
+        }
+    }
+}
+
+/// How pixels are turned into what is sent to the APA102s.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Output {
+    /// Exponent applied to each colour channel. 1.0 sends colours unchanged.
+    pub gamma: f32,
+    pub brightness_curve: BrightnessCurve,
+    /// Makes up brightness between the 32 global levels by scaling the colour channels,
+    /// so fades are smooth. Off by default, which sends the level rounded down and the colour
+    /// unchanged, as before it was added.
+    pub fine_brightness: bool,
+}
+
+impl Default for Output {
+    fn default() -> Self {
+        Self {
+            gamma: 1.0,
+            brightness_curve: BrightnessCurve::Linear,
+            fine_brightness: false,
+        }
+    }
+}
+
+impl Output {
+    /// The colour channels and the global brightness level from 0 to 31 sent for `pixel`.
+    pub fn encode(&self, pixel: Pixel) -> (Color, u8) {
+        let levels = self.brightness_curve.intensity(pixel.brightness) * MAX_BRIGHTNESS_LEVEL;
+        let (level, scale) = if self.fine_brightness {
+            let level = levels.ceil();
+            (level, if level > 0.0 { levels / level } else { 0.0 })
+        } else {
+            (levels.floor(), 1.0)
+        };
+        let channel =
+            |c: u8| ((f32::from(c) / 255.0).powf(self.gamma) * scale * 255.0).round() as u8;
+        let Color { red, green, blue } = pixel.color;
+        (
+            Color::rgb(channel(red), channel(green), channel(blue)),
+            level as u8,
+        )
+    }
+}
+
+/// Colour and brightness of one pixel. Brightness is from 0.0 to 1.0.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Pixel {
+    pub color: Color,
+    pub brightness: f32,
+}
+
+/// A chain of APA102 pixels on the Fan SHIM's LED pins.
+///
+/// Changes are kept in a frame buffer until `show`, which only writes to the pixels if the
+/// buffer changed since the last `show`. The pins are only held while writing, so other code
+/// such as the exit state can still use them.
+pub struct Led {
+    output: Output,
+    pixels: Vec<Pixel>,
+    /// The frame last written to the pixels, `None` before the first `show`.
+    shown: Option<Vec<Pixel>>,
+}
+
+impl Led {
+    /// A chain of `number_of_pixels` pixels, all off.
+    pub fn new(number_of_pixels: usize) -> Self {
+        Self {
+            output: Output::default(),
+            pixels: vec![Pixel::default(); number_of_pixels],
+            shown: None,
+        }
+    }
+
+    /// Sets how pixels are sent from the next `show` on.
+    pub fn set_output(&mut self, output: Output) {
+        if output != self.output {
+            self.output = output;
+            self.shown = None;
+        }
+    }
+
+    pub fn output(&self) -> Output {
+        self.output
+    }
+
+    pub fn len(&self) -> usize {
+        self.pixels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pixels.is_empty()
+    }
+
+    /// The pixel at `index` in the frame buffer, if there is one.
+    pub fn pixel(&self, index: usize) -> Option<Pixel> {
+        self.pixels.get(index).copied()
+    }
 
-    led.set_all_pixels(red, green, blue);
-    led.show().unwrap();
+    /// Sets the colour of the pixel at `index`, keeping its brightness. Out of range indices are ignored.
+    pub fn set_pixel(&mut self, index: usize, color: Color) {
+        if let Some(p) = self.pixels.get_mut(index) {
+            p.color = color;
+        }
+    }
+
+    /// Sets the brightness of the pixel at `index`. Out of range indices are ignored.
+    pub fn set_pixel_brightness(&mut self, index: usize, brightness: f32) {
+        if let Some(p) = self.pixels.get_mut(index) {
+            p.brightness = brightness.clamp(0.0, 1.0);
+        }
+    }
+
+    pub fn set_pixel_with_brightness(&mut self, index: usize, color: Color, brightness: f32) {
+        self.set_pixel(index, color);
+        self.set_pixel_brightness(index, brightness);
+    }
+
+    pub fn set_all_pixels(&mut self, color: Color, brightness: f32) {
+        for index in 0..self.len() {
+            self.set_pixel_with_brightness(index, color, brightness);
+        }
+    }
+
+    /// Turns all pixels off.
+    pub fn clear(&mut self) {
+        self.set_all_pixels(Color::BLACK, 0.0);
+    }
+
+    /// Whether the frame buffer differs from what the pixels show.
+    pub fn is_changed(&self) -> bool {
+        self.shown.as_ref() != Some(&self.pixels)
+    }
+
+    /// Writes the frame buffer to the pixels, unless they already show it.
+    pub fn show(&mut self) -> blinkt::Result<()> {
+        if !self.is_changed() {
+            return Ok(());
+        }
+
+        let mut blinkt = Blinkt::with_settings(LED_DATA_PIN, LED_CLOCK_PIN, self.len())?;
+        blinkt.set_clear_on_drop(false);
+        for (index, &p) in self.pixels.iter().enumerate() {
+            let (Color { red, green, blue }, level) = self.output.encode(p);
+            // Blinkt truncates the brightness to a level, so aim for the middle of the level.
+            let brightness = (f32::from(level) + 0.5) / MAX_BRIGHTNESS_LEVEL;
+            blinkt.set_pixel_rgbb(index, red, green, blue, brightness.min(1.0));
+        }
+        blinkt.show()?;
+        self.shown = Some(self.pixels.clone());
+        Ok(())
+    }
+}
+
+pub fn set_led_rgb(red: u8, green: u8, blue: u8) {
+    set_led_rgb_brightness(red, green, blue, 1.0);
 }
 
 pub fn set_led_rgb_brightness(red: u8, green: u8, blue: u8, brightness: f32) {
-    let mut led = Blinkt::with_settings(LED_DATA_PIN, LED_CLOCK_PIN, LED_NUMBER_OF_PIXELS).unwrap();
-    led.set_clear_on_drop(false);
+    try_set_led_rgb_brightness(red, green, blue, brightness).unwrap();
+}
+
+/// Like `set_led_rgb_brightness`, but returns errors instead of panicking.
+pub fn try_set_led_rgb_brightness(
+    red: u8,
+    green: u8,
+    blue: u8,
+    brightness: f32,
+) -> blinkt::Result<()> {
+    let mut led = Led::new(FAN_SHIM_PIXELS);
+    led.set_all_pixels(Color::rgb(red, green, blue), brightness);
+    led.show()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{BrightnessCurve, Led, Output, Pixel};
+    use crate::color::Color;
+
+    #[test]
+    fn only_changed_frames_need_showing() {
+        let mut led = Led::new(3);
+        assert!(led.is_changed());
+
+        led.set_pixel_with_brightness(1, Color::rgb(255, 0, 0), 0.5);
+        led.set_pixel(7, Color::rgb(0, 0, 255));
+        assert_eq!(
+            led.pixel(1),
+            Some(Pixel {
+                color: Color::rgb(255, 0, 0),
+                brightness: 0.5
+            })
+        );
+        assert_eq!(led.pixel(0), Some(Pixel::default()));
+        assert_eq!(led.pixel(7), None);
+
+        // As if shown.
+        led.shown = Some(led.pixels.clone());
+        led.set_pixel(1, Color::rgb(255, 0, 0));
+        assert!(!led.is_changed());
+        led.set_pixel_brightness(2, 0.1);
+        assert!(led.is_changed());
+    }
+
+    #[test]
+    fn encodes_brightness_between_levels_in_the_channels() {
+        let pixel = |brightness| Pixel {
+            color: Color::rgb(255, 128, 0),
+            brightness,
+        };
+        let coarse = Output::default();
+        // Both fall into the same global level.
+        assert_eq!(coarse.encode(pixel(0.1)), coarse.encode(pixel(0.12)));
+
+        let fine = Output {
+            fine_brightness: true,
+            ..Output::default()
+        };
+        assert_eq!(fine.encode(pixel(0.1)), (Color::rgb(198, 99, 0), 4));
+        assert_eq!(fine.encode(pixel(0.12)), (Color::rgb(237, 119, 0), 4));
+        assert_eq!(fine.encode(pixel(1.0)), (Color::rgb(255, 128, 0), 31));
+        assert_eq!(fine.encode(pixel(0.0)), (Color::BLACK, 0));
+    }
+
+    #[test]
+    fn applies_gamma_and_perceptual_brightness() {
+        let output = Output {
+            gamma: 2.2,
+            brightness_curve: BrightnessCurve::Perceptual,
+            fine_brightness: false,
+        };
+        let (color, level) = output.encode(Pixel {
+            color: Color::rgb(255, 128, 0),
+            brightness: 0.5,
+        });
+        assert_eq!(color, Color::rgb(255, 56, 0));
+        // Half the lightness is under a fifth of the light.
+        assert_eq!(level, 5);
 
-    led.set_all_pixels_rgbb(red, green, blue, brightness);
-    led.show().unwrap();
+        assert_eq!(BrightnessCurve::Perceptual.intensity(0.0), 0.0);
+        assert!((BrightnessCurve::Perceptual.intensity(1.0) - 1.0).abs() < 1e-6);
+    }
 }
@@ -1,23 +1,59 @@
-use crate::config::FanshimConfig;
-use log::debug;
+use crate::cancel::CancellationToken;
+use crate::color::Color;
+use crate::config::{ExitState, FanshimConfig};
+use crate::cpu::Hertz;
+use crate::dimmer::Dimmer;
+use crate::fan::SpinUp;
+use crate::health::{Health, HealthMonitor};
+use crate::history::History;
+use crate::hooks::{Event, Trigger};
+use crate::notify::{Notifier, NotifyState};
+use crate::ramp::{Direction, Ramp};
+use crate::schedule::Settings;
+use crate::status::{Indication, Pattern};
+use crate::throttle::{Condition, Throttled, Transition};
+use log::{debug, error, info, warn};
 #[cfg(test)]
 use mockall::automock;
-use std::thread;
-use std::time::Duration;
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{mem, thread};
 
+#[cfg(feature = "async")]
+pub mod async_shim;
 pub mod button;
+pub mod cancel;
+pub mod clock;
+pub mod color;
 pub mod config;
 pub mod cpu;
+pub mod dimmer;
 pub mod error;
+pub mod exit;
 pub mod fan;
+pub mod filter;
+pub mod guard;
+pub mod health;
+pub mod history;
+pub mod hooks;
 pub mod led;
+pub mod notify;
+pub mod observer;
+pub mod ramp;
+pub mod replay;
+pub mod schedule;
+pub mod sim;
+pub mod status;
+pub mod throttle;
+#[cfg(feature = "webhooks")]
+pub mod webhook;
 
 pub type Result<T> = std::result::Result<T, error::FanshimError>;
 
 const MILLI_CELSIUS_IN_CELSIUS: i32 = 1000;
 
 /// Temperature in millidegrees celsius. Multiply by 1000 to get "regular" celsius.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
 pub struct MilliCelsius(pub i32);
 
 pub fn initial_setup<T>(config: &FanshimConfig, s: &mut T)
@@ -27,104 +63,749 @@ where
     let temp = s.get_cpu_temperature();
     if temp.0 > config.enable_fan_at_temperature.0 {
         s.turn_fan_on();
-        s.set_led_rgb_brightness(
-            config.led_on_color.0,
-            config.led_on_color.1,
-            config.led_on_color.2,
-            config.led_brightness,
-        );
+        set_led(s, config.led_on_color, config.led_brightness);
     } else {
         s.turn_fan_off();
-        s.set_led_rgb_brightness(
-            config.led_off_color.0,
-            config.led_off_color.1,
-            config.led_off_color.2,
-            config.led_brightness,
-        );
+        set_led(s, config.led_off_color, config.led_brightness);
     }
 }
 
-pub fn main_loop<T>(config: &FanshimConfig, s: &mut T)
-where
-    T: ShimLayer,
-{
-    let mut number_of_sequential_above_reads = 0;
-    let mut number_of_sequential_below_reads = 0;
-    loop {
-        let cpu_temperature = s.get_cpu_temperature();
-        debug!("Temp: {:?}", cpu_temperature.0);
+/// The state of the fan control between readings.
+///
+/// `main_loop` runs it until cancelled. Callers with a scheduler of their own can call `tick`
+/// for each reading instead, waiting `delay_between_readings` of the config in between, and
+/// the kick-start time `tick` returns before `end_kick_start`.
+/// What it reads and decides can be followed with `subscribe`.
+pub struct Controller {
+    config: FanshimConfig,
+    number_of_sequential_above_reads: i32,
+    number_of_sequential_below_reads: i32,
+    history: History,
+    last_recorded_reading: Option<u64>,
+    filter: Box<dyn filter::TemperatureFilter>,
+    guard: guard::FanGuard,
+    spin_up: SpinUp,
+    /// The duty cycle to drop to once the kick-start `tick` began has ended.
+    kick_started: Option<f32>,
+    ramp: Ramp,
+    monitor: HealthMonitor,
+    dimmer: Dimmer,
+    first_reading: bool,
+    led_flash: bool,
+    alert_shown: bool,
+    alarm: bool,
+    active_window: Option<usize>,
+    /// Whether the config was reloaded since the last reading, so the speed limit and LED
+    /// settings are applied again.
+    reloaded: bool,
+    last_load: Option<f32>,
+    throttled: Throttled,
+    last_throttle_check: Option<SystemTime>,
+    last_temperature: Option<MilliCelsius>,
+    subscribers: observer::Subscribers,
+}
+
+impl Controller {
+    pub fn new(config: &FanshimConfig) -> Self {
+        Self {
+            config: config.clone(),
+            number_of_sequential_above_reads: 0,
+            number_of_sequential_below_reads: 0,
+            history: open_history(&config.history),
+            last_recorded_reading: None,
+            filter: filter::from_config(config.filter, config.delay_between_readings),
+            guard: guard::FanGuard::new(config),
+            spin_up: SpinUp::new(config),
+            kick_started: None,
+            ramp: Ramp::new(config),
+            monitor: HealthMonitor::new(config),
+            dimmer: Dimmer::new(config),
+            first_reading: true,
+            led_flash: false,
+            alert_shown: false,
+            alarm: false,
+            active_window: None,
+            reloaded: false,
+            last_load: None,
+            throttled: Throttled::default(),
+            last_throttle_check: None,
+            last_temperature: None,
+            subscribers: observer::Subscribers::default(),
+        }
+    }
+
+    pub fn config(&self) -> &FanshimConfig {
+        &self.config
+    }
+
+    /// Receives the events of all following readings, until the receiver is dropped. Events
+    /// are dropped while `observer::CAPACITY` of them are waiting to be received.
+    pub fn subscribe(&mut self) -> Receiver<observer::Event> {
+        self.subscribers.subscribe()
+    }
 
-        let fan_enabled = s.fan_is_enabled();
+    /// Uses `config` from the next reading on.
+    ///
+    /// The fan keeps running as it is until the new thresholds say otherwise, and the minimum
+    /// on and off times still count from the last change. The ramp, the health monitor, the LED
+    /// and the history carry over, as does the filter unless its settings changed.
+    pub fn reload(&mut self, config: &FanshimConfig) {
+        if config.filter != self.config.filter
+            || config.delay_between_readings != self.config.delay_between_readings
+        {
+            self.filter = filter::from_config(config.filter, config.delay_between_readings);
+        }
+        if config.history.capacity != self.config.history.capacity
+            || config.history.file != self.config.history.file
+        {
+            self.history = open_history(&config.history);
+        }
+        self.guard.reconfigure(config);
+        self.spin_up = SpinUp::new(config);
+        self.ramp.reconfigure(config);
+        self.monitor.reconfigure(config);
+        self.dimmer.reconfigure(config);
+        // The schedule may have changed, so the active section is looked up again.
+        self.active_window = None;
+        self.reloaded = true;
+        self.config = config.clone();
+        self.subscribers.emit(observer::Event::ConfigReloaded {
+            profile: config.profile.clone(),
+        });
+    }
+
+    /// Controls the fan until `token` is cancelled. Can be called again afterwards, e.g. after a
+    /// `reload`.
+    pub fn run_until_cancelled<T>(&mut self, s: &mut T, token: &CancellationToken)
+    where
+        T: ShimLayer,
+    {
+        loop {
+            if let Some(kick) = self.tick(s) {
+                s.sleep(kick);
+                self.end_kick_start(s);
+            }
+            s.sleep(self.config.delay_between_readings);
+
+            if token.is_cancelled() {
+                return;
+            }
+        }
+    }
+
+    /// Takes one reading and acts on it, without waiting for the next one.
+    ///
+    /// Returns how long to wait before `end_kick_start` when the fan is being kick-started.
+    /// Otherwise the fan runs at full speed until the next `tick`.
+    pub fn tick<T>(&mut self, s: &mut T) -> Option<Duration>
+    where
+        T: ShimLayer,
+    {
+        self.end_kick_start(s);
+        let config = &self.config;
+        let raw_temperature = s.get_cpu_temperature();
+        let cpu_temperature = self.filter.filter(raw_temperature);
+        let load = s.get_cpu_load();
+        let frequency = s.get_cpu_frequency();
+        debug!(
+            temp = raw_temperature.0, filtered_temp = cpu_temperature.0;
+            "Temp: {:?}, filtered: {:?}, load: {:?}, frequency: {:?}",
+            raw_temperature.0, cpu_temperature.0, load, frequency
+        );
+        self.subscribers.emit(observer::Event::TemperatureRead {
+            raw: raw_temperature,
+            filtered: cpu_temperature,
+        });
+
+        self.ramp.sync(s.fan_is_enabled());
+        let target = self.ramp.target();
+        // Whether the controller wants the fan on, even if it is still ramping there.
+        let fan_enabled = target > 0.0;
+        if self.first_reading {
+            // Left by `initial_setup`.
+            self.dimmer
+                .assume_shown(fan_status_color(config, fan_enabled), config.led_brightness);
+            self.first_reading = false;
+        }
+
+        let now = s.now();
+        let window = if config.schedule.is_empty() {
+            None
+        } else {
+            schedule::active_window(&config.schedule, schedule::local_time(now))
+        };
+        let settings = Settings::new(config, window.map(|i| &config.schedule[i]));
+        let duty = self.spin_up.duty(settings.max_fan_speed);
+        if let Some(last) = self.last_temperature {
+            let enable = settings.enable_fan_at_temperature.0;
+            let disable = settings.disable_fan_at_temperature.0;
+            let crossings = [
+                (
+                    observer::Threshold::Enable,
+                    last.0 > enable,
+                    cpu_temperature.0 > enable,
+                ),
+                (
+                    observer::Threshold::Disable,
+                    last.0 >= disable,
+                    cpu_temperature.0 >= disable,
+                ),
+            ];
+            for (threshold, was_above, above) in crossings {
+                if above != was_above {
+                    self.subscribers.emit(observer::Event::ThresholdCrossed {
+                        threshold,
+                        above,
+                        temperature: cpu_temperature,
+                    });
+                }
+            }
+        }
+        self.last_temperature = Some(cpu_temperature);
+        if window != self.active_window {
+            match (self.active_window, window) {
+                (_, Some(i)) => info!("Schedule {} is active", config.schedule[i]),
+                (Some(i), None) => info!("Schedule {} has ended", config.schedule[i]),
+                (None, None) => (),
+            }
+        }
+        if window != self.active_window || mem::take(&mut self.reloaded) {
+            // Applies the new speed limit and LED brightness straight away.
+            if fan_enabled && duty > 0.0 {
+                set_fan_on(config, &settings, duty, &mut self.ramp, &mut self.dimmer);
+            } else {
+                set_fan_off(config, &settings, &mut self.ramp, &mut self.dimmer);
+            }
+            self.active_window = window;
+        }
+
+        match self.monitor.update(now, cpu_temperature, self.ramp.duty()) {
+            Some(Health::FanSuspectedFailed) => warn!(
+                temp = cpu_temperature.0, health = "fan_suspected_failed";
+                "The fan is suspected to have failed: the temperature rises as fast as with the fan off"
+            ),
+            Some(Health::Ok) => {
+                info!(health = "ok"; "The fan is no longer suspected to have failed")
+            }
+            None => (),
+        }
+
+        let throttle_check_due = config.throttle.seconds_between_checks > 0
+            && self.last_throttle_check.is_none_or(|t| {
+                now.duration_since(t).map_or(true, |d| {
+                    d.as_secs() >= config.throttle.seconds_between_checks
+                })
+            });
+        if throttle_check_due {
+            self.last_throttle_check = Some(now);
+            if let Some(t) = s.get_throttled() {
+                for transition in t.transitions(self.throttled) {
+                    match transition {
+                        Transition::Started(c) => warn!("Firmware reports {}", c),
+                        Transition::Ended(c) => info!("Firmware no longer reports {}", c),
+                        Transition::Occurred(c) => {
+                            warn!("Firmware reports {} has occurred since boot", c)
+                        }
+                    }
+                }
+                self.throttled = t;
+            }
+        }
 
-        let cpu_temp_is_above_threshold = cpu_temperature.0 > config.enable_fan_at_temperature.0;
+        let cpu_temp_is_above_threshold =
+            cpu_temperature.0 > settings.enable_fan_at_temperature.0 && duty > 0.0;
         if cpu_temp_is_above_threshold && !fan_enabled {
-            number_of_sequential_above_reads += 1;
+            self.number_of_sequential_above_reads += 1;
         } else {
-            number_of_sequential_above_reads = 0;
+            self.number_of_sequential_above_reads = 0;
         }
 
         let cpu_temp_is_below_disable_threshold =
-            cpu_temperature.0 < config.disable_fan_at_temperature.0;
+            cpu_temperature.0 < settings.disable_fan_at_temperature.0;
         if cpu_temp_is_below_disable_threshold && fan_enabled {
-            number_of_sequential_below_reads += 1;
+            self.number_of_sequential_below_reads += 1;
         } else {
-            number_of_sequential_below_reads = 0;
+            self.number_of_sequential_below_reads = 0;
         }
 
-        let fan_should_enable =
-            number_of_sequential_above_reads >= config.number_of_readings_before_action;
-        let fan_should_disable =
-            number_of_sequential_below_reads >= config.number_of_readings_before_action;
+        // A jump in load heats the CPU soon, so the fan starts before the temperature catches up
+        // if it is already past the disable threshold.
+        let load_jumped = match (self.last_load, load) {
+            (Some(last), Some(load)) => {
+                config.preemptive_load_increase > 0.0
+                    && load - last >= config.preemptive_load_increase
+            }
+            _ => false,
+        };
+        self.last_load = load;
+        let spin_up_early = load_jumped
+            && !fan_enabled
+            && duty > 0.0
+            && cpu_temperature.0 >= settings.disable_fan_at_temperature.0;
+
+        let fan_should_enable = (self.number_of_sequential_above_reads
+            >= config.number_of_readings_before_action
+            || spin_up_early)
+            && self.guard.allows(now, true);
+        let fan_should_disable = self.number_of_sequential_below_reads
+            >= config.number_of_readings_before_action
+            && self.guard.allows(now, false);
+        if fan_should_enable || fan_should_disable {
+            self.guard.record(now);
+        }
+
+        let status = s.get_status();
+        let mut alarms = Vec::new();
+        if self.monitor.health() == Health::FanSuspectedFailed {
+            alarms.push("fan suspected failed".to_owned());
+        }
+        if self.throttled.any_active() {
+            alarms.push(self.throttled.to_string());
+        }
+        if let Some(indication) = &status {
+            alarms.push(indication.name.clone());
+        }
+        let was_alarm = self.alarm;
+        self.alarm = !alarms.is_empty();
+        if self.alarm != was_alarm {
+            let event = if self.alarm {
+                Event::Alarm
+            } else {
+                Event::AlarmClear
+            };
+            s.run_hook(trigger(
+                config,
+                event,
+                cpu_temperature,
+                fan_enabled,
+                &alarms,
+            ));
+            self.subscribers.emit(observer::Event::Alarm {
+                active: self.alarm,
+                alarms: alarms.clone(),
+            });
+        }
 
         if fan_should_enable {
-            s.turn_fan_on();
-            s.set_led_rgb_brightness(
-                config.led_on_color.0,
-                config.led_on_color.1,
-                config.led_on_color.2,
-                config.led_brightness,
-            );
-            number_of_sequential_below_reads = 0;
-            number_of_sequential_above_reads = 0;
+            if self.number_of_sequential_above_reads >= config.number_of_readings_before_action {
+                info!(temp = cpu_temperature.0, fan = "on"; "Turning fan on at {:?}", cpu_temperature);
+            } else {
+                info!(
+                    temp = cpu_temperature.0, fan = "on";
+                    "Turning fan on early at {:?}, CPU load rose to {:.0}%",
+                    cpu_temperature, load.unwrap_or_default() * 100.0
+                );
+            }
+            set_fan_on(config, &settings, duty, &mut self.ramp, &mut self.dimmer);
+            s.run_hook(trigger(
+                config,
+                Event::FanOn,
+                cpu_temperature,
+                true,
+                &alarms,
+            ));
+            self.number_of_sequential_below_reads = 0;
+            self.number_of_sequential_above_reads = 0;
         } else if fan_should_disable {
-            s.turn_fan_off();
-            s.set_led_rgb_brightness(
-                config.led_off_color.0,
-                config.led_off_color.1,
-                config.led_off_color.2,
-                config.led_brightness,
-            );
-            number_of_sequential_below_reads = 0;
-            number_of_sequential_above_reads = 0;
+            info!(temp = cpu_temperature.0, fan = "off"; "Turning fan off at {:?}", cpu_temperature);
+            set_fan_off(config, &settings, &mut self.ramp, &mut self.dimmer);
+            s.run_hook(trigger(
+                config,
+                Event::FanOff,
+                cpu_temperature,
+                false,
+                &alarms,
+            ));
+            self.number_of_sequential_below_reads = 0;
+            self.number_of_sequential_above_reads = 0;
+        }
+        if self.ramp.target() != target {
+            self.subscribers.emit(observer::Event::FanStateChanged {
+                enabled: self.ramp.target() > 0.0,
+                duty: self.ramp.target(),
+            });
         }
 
-        s.sleep(config.delay_between_readings);
+        // A failed fan, then throttling, then the system status, over the fan status.
+        self.led_flash = !self.led_flash;
+        let flashing = if self.led_flash {
+            settings.led_brightness
+        } else {
+            0.0
+        };
+        let alert = if self.monitor.health() == Health::FanSuspectedFailed {
+            Some((config.health.led_color, flashing))
+        } else if let Some(color) = alert_color(config, self.throttled) {
+            Some((color, settings.led_brightness))
+        } else {
+            status.as_ref().map(|i| match i.pattern {
+                Pattern::Solid => (i.color, settings.led_brightness),
+                Pattern::Flash => (i.color, flashing),
+            })
+        };
+        if let Some((color, brightness)) = alert {
+            self.dimmer.request(color, brightness);
+            self.dimmer.wake();
+        } else if self.alert_shown {
+            show_fan_status(config, &settings, fan_enabled, &mut self.dimmer);
+        }
+        self.alert_shown = alert.is_some();
+        if s.button_pressed() {
+            self.dimmer.wake();
+        }
+        if let Some((color, brightness)) = self.dimmer.update(now) {
+            if self.dimmer.is_idle(now) {
+                debug!("Turning the LED off until something changes");
+            }
+            set_led(s, color, brightness);
+            self.subscribers
+                .emit(observer::Event::LedChanged { color, brightness });
+        }
 
-        #[cfg(test)]
-        if s.should_exit() {
-            return;
+        let was_running = self.ramp.duty() > 0.0;
+        let mut kick = None;
+        if let Some(duty) = self.ramp.step(config.delay_between_readings) {
+            kick = self.spin_up.kick(was_running, duty);
+            match kick {
+                Some(kick) => {
+                    debug!("Kick-starting fan for {:?}", kick);
+                    s.turn_fan_on();
+                    self.kick_started = Some(duty);
+                }
+                None => set_fan_duty(s, duty),
+            }
+        }
+
+        let timestamp = unix_timestamp(now);
+        let event = if fan_should_enable {
+            Some(history::Event::FanOn)
+        } else if fan_should_disable {
+            Some(history::Event::FanOff)
+        } else if self
+            .last_recorded_reading
+            .is_none_or(|t| timestamp >= t + config.history.seconds_between_readings)
+        {
+            self.last_recorded_reading = Some(timestamp);
+            Some(history::Event::Reading)
+        } else {
+            None
+        };
+        if let Some(event) = event {
+            let entry = history::Entry {
+                timestamp,
+                temperature: raw_temperature,
+                duty: self.ramp.duty(),
+                event,
+            };
+            if let Err(e) = self.history.record(entry) {
+                warn!("Unable to record history: {}", e);
+            }
+        }
+
+        s.notify(NotifyState::Status(status_line(
+            raw_temperature,
+            &self.ramp,
+            self.monitor.health(),
+            self.throttled,
+            config.profile.as_deref(),
+        )));
+        s.notify(NotifyState::Watchdog);
+        kick
+    }
+
+    /// Drops a kick-started fan to the duty cycle it is ramping to.
+    pub fn end_kick_start<T>(&mut self, s: &mut T)
+    where
+        T: ShimLayer,
+    {
+        if let Some(duty) = self.kick_started.take() {
+            set_fan_duty(s, duty);
         }
     }
 }
 
+/// Controls the fan until `token` is cancelled.
+pub fn main_loop<T>(config: &FanshimConfig, s: &mut T, token: &CancellationToken)
+where
+    T: ShimLayer,
+{
+    Controller::new(config).run_until_cancelled(s, token);
+}
+
+fn trigger(
+    config: &FanshimConfig,
+    event: Event,
+    temperature: MilliCelsius,
+    fan_enabled: bool,
+    alarms: &[String],
+) -> Trigger {
+    Trigger {
+        event,
+        temperature,
+        fan_enabled,
+        profile: config.profile.clone(),
+        alarms: alarms.to_vec(),
+    }
+}
+
+/// Ramps the fan to `duty` and shows it on the LED.
+fn set_fan_on(
+    config: &FanshimConfig,
+    settings: &Settings,
+    duty: f32,
+    ramp: &mut Ramp,
+    dimmer: &mut Dimmer,
+) {
+    ramp.set_target(duty);
+    dimmer.request(config.led_on_color, settings.led_brightness);
+}
+
+fn set_fan_off(config: &FanshimConfig, settings: &Settings, ramp: &mut Ramp, dimmer: &mut Dimmer) {
+    ramp.set_target(0.0);
+    dimmer.request(config.led_off_color, settings.led_brightness);
+}
+
+/// Shows on the LED whether the fan is on.
+fn show_fan_status(
+    config: &FanshimConfig,
+    settings: &Settings,
+    fan_enabled: bool,
+    dimmer: &mut Dimmer,
+) {
+    dimmer.request(
+        fan_status_color(config, fan_enabled),
+        settings.led_brightness,
+    );
+}
+
+fn fan_status_color(config: &FanshimConfig, fan_enabled: bool) -> Color {
+    if fan_enabled {
+        config.led_on_color
+    } else {
+        config.led_off_color
+    }
+}
+
+fn set_led<T>(s: &mut T, color: Color, brightness: f32)
+where
+    T: ShimLayer,
+{
+    s.set_led_rgb_brightness(color.red, color.green, color.blue, brightness);
+}
+
+/// The LED colour for the active throttle conditions, if one is configured.
+fn alert_color(config: &FanshimConfig, throttled: Throttled) -> Option<Color> {
+    let throttle = &config.throttle;
+    let under_voltage = throttle
+        .under_voltage_led_color
+        .filter(|_| throttled.is_active(Condition::UnderVoltage));
+    let throttled_color = throttle.throttled_led_color.filter(|_| {
+        throttled.is_active(Condition::FrequencyCapped)
+            || throttled.is_active(Condition::Throttled)
+            || throttled.is_active(Condition::SoftTemperatureLimit)
+    });
+    under_voltage.or(throttled_color)
+}
+
+/// Sets the fan to `duty`.
+///
+/// `tick` kicks a fan starting from standstill at full speed first, as it may stall at a low
+/// duty cycle.
+fn set_fan_duty<T>(s: &mut T, duty: f32)
+where
+    T: ShimLayer,
+{
+    if duty >= 1.0 {
+        s.turn_fan_on();
+    } else if duty > 0.0 {
+        s.set_fan_duty(duty);
+    } else {
+        s.turn_fan_off();
+    }
+}
+
+fn open_history(config: &config::History) -> History {
+    match &config.file {
+        Some(path) => History::with_file(config.capacity, path).unwrap_or_else(|e| {
+            warn!(
+                "Unable to open history file {}: {}. Keeping history in memory only.",
+                path.display(),
+                e
+            );
+            History::new(config.capacity)
+        }),
+        None => History::new(config.capacity),
+    }
+}
+
+fn unix_timestamp(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+fn status_line(
+    temperature: MilliCelsius,
+    ramp: &Ramp,
+    health: Health,
+    throttled: Throttled,
+    profile: Option<&str>,
+) -> String {
+    let mut line = format!(
+        "Temperature {:.1}°C, fan {}",
+        f64::from(temperature.0) / f64::from(MILLI_CELSIUS_IN_CELSIUS),
+        speed(ramp.duty())
+    );
+    match ramp.direction() {
+        Some(Direction::Up) => line.push_str(&format!(", ramping up to {}", speed(ramp.target()))),
+        Some(Direction::Down) => {
+            line.push_str(&format!(", ramping down to {}", speed(ramp.target())))
+        }
+        None => (),
+    }
+    if health == Health::FanSuspectedFailed {
+        line.push_str(", fan suspected failed");
+    }
+    if throttled.any_active() {
+        line.push_str(&format!(", {}", throttled));
+    }
+    if let Some(profile) = profile {
+        line.push_str(&format!(", profile {}", profile));
+    }
+    line
+}
+
+fn speed(duty: f32) -> String {
+    if duty >= 1.0 {
+        "on".to_owned()
+    } else if duty > 0.0 {
+        format!("at {:.0}%", duty * 100.0)
+    } else {
+        "off".to_owned()
+    }
+}
+
 #[cfg_attr(test, automock)]
 pub trait ShimLayer {
     fn get_cpu_temperature(&mut self) -> MilliCelsius;
+    /// Fraction of the time the CPUs were busy since the last call, from 0.0 to 1.0.
+    /// `None` if not known, such as on the first call.
+    fn get_cpu_load(&mut self) -> Option<f32>;
+    /// Highest current frequency of all CPUs, if known.
+    fn get_cpu_frequency(&mut self) -> Option<Hertz>;
+    /// Throttle flags reported by the firmware, if known.
+    fn get_throttled(&mut self) -> Option<Throttled>;
+    /// What the LED should show for the system status, if a status rule is active.
+    fn get_status(&mut self) -> Option<Indication>;
 
     fn fan_is_enabled(&mut self) -> bool;
     fn turn_fan_on(&mut self);
     fn turn_fan_off(&mut self);
+    /// Runs the fan at `duty`, from 0.0 (off) to 1.0 (full speed).
+    fn set_fan_duty(&mut self, duty: f32);
     fn set_led_rgb_brightness(&mut self, r: u8, g: u8, b: u8, brightness: f32);
+    /// Whether the button was pressed since the last call.
+    fn button_pressed(&mut self) -> bool;
+    /// Runs the hook command configured for the event, without waiting for it.
+    fn run_hook(&mut self, trigger: Trigger);
 
     fn sleep(&mut self, dur: Duration);
+    /// Current time, which only moves with `sleep` in simulations and tests.
+    fn now(&self) -> SystemTime;
 
-    fn should_exit(&mut self) -> bool;
+    /// Reports the daemon state to the service manager.
+    fn notify(&mut self, state: NotifyState);
 }
 
-pub struct RealShim {}
+/// Drives the real Fan SHIM hardware.
+///
+/// The configured exit state is applied when this is dropped, including while unwinding from a panic.
+pub struct RealShim {
+    notifier: Notifier,
+    load_meter: cpu::LoadMeter,
+    fan: fan::Fan,
+    led: led::Led,
+    button_presses: button::PressLatch,
+    status_rules: Vec<status::Rule>,
+    status: Option<status::Monitor>,
+    hooks: hooks::Hooks<hooks::CommandExecutor>,
+    #[cfg(feature = "webhooks")]
+    webhook: Option<webhook::Webhook>,
+    exit_state: ExitState,
+}
 impl RealShim {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            notifier: Notifier::from_env(),
+            load_meter: cpu::LoadMeter::new(),
+            fan: fan::Fan::new(),
+            led: led::Led::new(led::FAN_SHIM_PIXELS),
+            button_presses: button::PressLatch::default(),
+            status_rules: Vec::new(),
+            status: None,
+            hooks: hooks::Hooks::new(hooks::CommandExecutor),
+            #[cfg(feature = "webhooks")]
+            webhook: None,
+            exit_state: ExitState::default(),
+        }
+    }
+
+    pub fn set_exit_state(&mut self, exit_state: ExitState) {
+        self.exit_state = exit_state;
+    }
+
+    /// How often systemd expects a watchdog ping, if the watchdog is enabled.
+    pub fn watchdog_interval(&self) -> Option<Duration> {
+        self.notifier.watchdog_interval()
+    }
+
+    /// The button, reporting its presses to `button_pressed`.
+    pub fn button(&self) -> rppal::gpio::Result<button::Button> {
+        button::Button::with_latch(self.button_presses.clone())
+    }
+
+    pub fn set_hooks(&mut self, config: &config::Hooks) {
+        self.hooks.set_config(config);
+    }
+
+    /// Sends notifications as configured, keeping the queue and rate limit if unchanged.
+    #[cfg(feature = "webhooks")]
+    pub fn set_webhook(&mut self, config: &config::Webhook) {
+        if self.webhook.as_ref().map(webhook::Webhook::config) != Some(config) {
+            self.webhook = Some(webhook::Webhook::new(config));
+        }
+    }
+
+    #[cfg(not(feature = "webhooks"))]
+    pub fn set_webhook(&mut self, config: &config::Webhook) {
+        if config.url.is_some() {
+            warn!("Webhook notifications need a build with the `webhooks` feature");
+        }
+    }
+
+    /// Starts checking `rules`, unless they are already checked.
+    pub fn set_status_rules(&mut self, rules: &[status::Rule]) {
+        if rules != self.status_rules.as_slice() {
+            self.status_rules = rules.to_vec();
+            // Dropping the monitor stops it.
+            self.status = None;
+            if !rules.is_empty() {
+                let sources = rules.iter().map(status::Source::from).collect();
+                self.status = Some(status::Monitor::start(sources));
+            }
+        }
+    }
+
+    /// Sets how colours and brightness are sent to the LED.
+    pub fn set_led_output(&mut self, output: led::Output) {
+        self.led.set_output(output);
+    }
+
+    /// Sets the number of pixels chained to the LED pins.
+    pub fn set_led_pixels(&mut self, number_of_pixels: usize) {
+        if number_of_pixels != self.led.len() {
+            let mut led = led::Led::new(number_of_pixels);
+            led.set_output(self.led.output());
+            self.led = led;
+        }
     }
 }
 impl Default for RealShim {
@@ -133,42 +814,104 @@ impl Default for RealShim {
     }
 }
 
+impl Drop for RealShim {
+    fn drop(&mut self) {
+        self.fan.release();
+        if let Err(e) = exit::apply_exit_state(self.exit_state, self.led.len()) {
+            error!("Unable to set exit state {:?}: {}", self.exit_state, e);
+        }
+    }
+}
+
 impl ShimLayer for RealShim {
     fn get_cpu_temperature(&mut self) -> MilliCelsius {
         cpu::get_cpu_temperature()
     }
 
+    fn get_cpu_load(&mut self) -> Option<f32> {
+        self.load_meter.load().ok().flatten()
+    }
+
+    fn get_cpu_frequency(&mut self) -> Option<Hertz> {
+        cpu::get_cpu_frequency().ok()
+    }
+
+    fn get_throttled(&mut self) -> Option<Throttled> {
+        throttle::read().ok()
+    }
+
+    fn get_status(&mut self) -> Option<Indication> {
+        self.status.as_ref().and_then(status::Monitor::current)
+    }
+
     fn fan_is_enabled(&mut self) -> bool {
-        fan::fan_is_enabled()
+        self.fan.is_enabled()
     }
 
     fn turn_fan_on(&mut self) {
-        fan::turn_fan_full_on();
+        self.set_fan_duty(1.0);
     }
 
     fn turn_fan_off(&mut self) {
-        fan::turn_fan_off();
+        self.set_fan_duty(0.0);
+    }
+
+    fn set_fan_duty(&mut self, duty: f32) {
+        self.fan.set_duty(duty).expect("unable to get fan pin");
     }
 
     fn set_led_rgb_brightness(&mut self, r: u8, g: u8, b: u8, brightness: f32) {
-        led::set_led_rgb_brightness(r, g, b, brightness);
+        self.led.set_all_pixels(Color::rgb(r, g, b), brightness);
+        self.led.show().expect("unable to set LED");
+    }
+
+    fn button_pressed(&mut self) -> bool {
+        self.button_presses.take()
+    }
+
+    fn run_hook(&mut self, trigger: Trigger) {
+        self.hooks.fire(&trigger);
+        #[cfg(feature = "webhooks")]
+        if let Some(webhook) = &mut self.webhook {
+            webhook.notify(&trigger);
+        }
     }
 
     fn sleep(&mut self, dur: Duration) {
         thread::sleep(dur);
     }
 
-    fn should_exit(&mut self) -> bool {
-        false
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn notify(&mut self, state: NotifyState) {
+        if let Err(e) = self.notifier.notify(&state) {
+            warn!("Unable to notify service manager of {:?}: {}", state, e);
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::config::FanshimConfig;
-    use crate::{initial_setup, main_loop, MilliCelsius};
-    use mockall::predicate::eq;
+    use crate::cancel::CancellationToken;
+    use crate::clock::VirtualClock;
+    use crate::color::Color;
+    use crate::config::{FanshimConfig, FanshimInterimConfig};
+    use crate::filter::FilterConfig;
+    use crate::health::Health;
+    use crate::hooks::Event;
+    use crate::observer;
+    use crate::ramp::Ramp;
+    use crate::status::{Indication, Pattern};
+    use crate::throttle::Throttled;
+    use crate::{alert_color, initial_setup, main_loop, status_line, Controller, MilliCelsius};
+    use chrono::{Local, TimeZone};
+    use mockall::predicate::{always, eq};
     use mockall::*;
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
     fn temperature_above_enable() -> MilliCelsius {
         MilliCelsius(FanshimConfig::default().enable_fan_at_temperature.0 + 1_000)
@@ -186,30 +929,76 @@ mod test {
         MilliCelsius(FanshimConfig::default().disable_fan_at_temperature.0 - 1_000)
     }
 
-    fn test_setup() -> (FanshimConfig, Sequence, super::MockShimLayer) {
+    /// The clock the mock reports, moved forward by every expected `sleep`.
+    struct Context {
+        clock: Arc<Mutex<VirtualClock>>,
+        token: CancellationToken,
+        /// CPU loads the mock reports, one per reading. Unknown once empty.
+        loads: Arc<Mutex<VecDeque<f32>>>,
+        /// Whether the button was pressed, one per reading. Not pressed once empty.
+        presses: Arc<Mutex<VecDeque<bool>>>,
+        /// The system status, one per reading. None once empty.
+        statuses: Arc<Mutex<VecDeque<Option<Indication>>>>,
+        /// Events of the hooks run.
+        hooks: Arc<Mutex<Vec<Event>>>,
+    }
+
+    fn test_setup() -> (FanshimConfig, Sequence, super::MockShimLayer, Context) {
         let c = FanshimConfig::default();
         let seq = Sequence::new();
-        let mock = super::MockShimLayer::new();
-        (c, seq, mock)
+        let mut mock = super::MockShimLayer::new();
+        mock.expect_notify().return_const(());
+        let ctx = Context {
+            clock: Arc::new(Mutex::new(VirtualClock::new(UNIX_EPOCH))),
+            token: CancellationToken::new(),
+            loads: Arc::new(Mutex::new(VecDeque::new())),
+            presses: Arc::new(Mutex::new(VecDeque::new())),
+            statuses: Arc::new(Mutex::new(VecDeque::new())),
+            hooks: Arc::new(Mutex::new(Vec::new())),
+        };
+        let clock = Arc::clone(&ctx.clock);
+        mock.expect_now()
+            .returning(move || clock.lock().unwrap().now());
+        let loads = Arc::clone(&ctx.loads);
+        mock.expect_get_cpu_load()
+            .returning(move || loads.lock().unwrap().pop_front());
+        mock.expect_get_cpu_frequency().return_const(None);
+        mock.expect_get_throttled().return_const(None);
+        let statuses = Arc::clone(&ctx.statuses);
+        mock.expect_get_status()
+            .returning(move || statuses.lock().unwrap().pop_front().flatten());
+        let presses = Arc::clone(&ctx.presses);
+        mock.expect_button_pressed()
+            .returning(move || presses.lock().unwrap().pop_front().unwrap_or(false));
+        let hooks = Arc::clone(&ctx.hooks);
+        mock.expect_run_hook()
+            .returning(move |t| hooks.lock().unwrap().push(t.event));
+        (c, seq, mock, ctx)
     }
 
-    fn should_not_exit(mock: &mut super::MockShimLayer, seq: &mut Sequence) {
-        mock.expect_should_exit()
+    fn keep_running(mock: &mut super::MockShimLayer, seq: &mut Sequence, ctx: &Context) {
+        let clock = Arc::clone(&ctx.clock);
+        mock.expect_sleep()
             .times(1)
             .in_sequence(seq)
-            .return_const(false);
+            .returning(move |dur| clock.lock().unwrap().advance(dur));
     }
 
-    fn should_exit(mock: &mut super::MockShimLayer, seq: &mut Sequence) {
-        mock.expect_should_exit()
+    fn stop_after_sleep(mock: &mut super::MockShimLayer, seq: &mut Sequence, ctx: &Context) {
+        let clock = Arc::clone(&ctx.clock);
+        let token = ctx.token.clone();
+        mock.expect_sleep()
             .times(1)
             .in_sequence(seq)
-            .return_const(true);
+            .returning(move |dur| {
+                clock.lock().unwrap().advance(dur);
+                token.cancel();
+            });
     }
 
     #[test]
     fn initial_setup_enables_when_hot() {
-        let (c, mut seq, mut mock) = test_setup();
+        let (c, mut seq, mut mock, _) = test_setup();
 
         // GIVEN:
         mock.expect_get_cpu_temperature()
@@ -231,7 +1020,7 @@ mod test {
 
     #[test]
     fn initial_setup_disables_when_cold() {
-        let (c, mut seq, mut mock) = test_setup();
+        let (c, mut seq, mut mock, _) = test_setup();
 
         // GIVEN:
         mock.expect_get_cpu_temperature()
@@ -253,21 +1042,24 @@ mod test {
 
     #[test]
     fn fan_enables_above_temperature_and_after_count() {
-        let (c, mut seq, mut mock) = test_setup();
+        let (c, mut seq, mut mock, ctx) = test_setup();
 
         // GIVEN:
-        mock.expect_sleep().return_const(());
         mock.expect_get_cpu_temperature()
             .return_const(temperature_above_enable());
         mock.expect_fan_is_enabled().return_const(false);
-        let (r, g, b) = c.led_on_color;
+        let Color {
+            red: r,
+            green: g,
+            blue: b,
+        } = c.led_on_color;
         mock.expect_set_led_rgb_brightness()
             .with(eq(r), eq(g), eq(b), eq(c.led_brightness))
             .return_const(());
 
         // THEN:
         for _ in 1..c.number_of_readings_before_action {
-            should_not_exit(&mut mock, &mut seq);
+            keep_running(&mut mock, &mut seq, &ctx);
         }
 
         mock.expect_turn_fan_on()
@@ -275,29 +1067,32 @@ mod test {
             .in_sequence(&mut seq)
             .return_const(());
 
-        should_exit(&mut mock, &mut seq);
+        stop_after_sleep(&mut mock, &mut seq, &ctx);
 
         // WHEN:
-        main_loop(&c, &mut mock);
+        main_loop(&c, &mut mock, &ctx.token);
     }
 
     #[test]
     fn fan_disables_below_temperature_and_after_count() {
-        let (c, mut seq, mut mock) = test_setup();
+        let (c, mut seq, mut mock, ctx) = test_setup();
 
         // GIVEN:
-        mock.expect_sleep().return_const(());
         mock.expect_get_cpu_temperature()
             .return_const(temperature_below_disable());
         mock.expect_fan_is_enabled().return_const(true);
-        let (r, g, b) = c.led_off_color;
+        let Color {
+            red: r,
+            green: g,
+            blue: b,
+        } = c.led_off_color;
         mock.expect_set_led_rgb_brightness()
             .with(eq(r), eq(g), eq(b), eq(c.led_brightness))
             .return_const(());
 
         // THEN:
         for _ in 1..c.number_of_readings_before_action {
-            should_not_exit(&mut mock, &mut seq);
+            keep_running(&mut mock, &mut seq, &ctx);
         }
 
         mock.expect_turn_fan_off()
@@ -305,59 +1100,60 @@ mod test {
             .in_sequence(&mut seq)
             .return_const(());
 
-        should_exit(&mut mock, &mut seq);
+        stop_after_sleep(&mut mock, &mut seq, &ctx);
 
         // WHEN:
-        main_loop(&c, &mut mock);
+        main_loop(&c, &mut mock, &ctx.token);
     }
 
     #[test]
     fn fan_does_not_enable_when_below_temperature() {
-        let (c, mut seq, mut mock) = test_setup();
+        let (c, mut seq, mut mock, ctx) = test_setup();
 
         // GIVEN:
-        mock.expect_sleep().return_const(());
         mock.expect_get_cpu_temperature()
             .return_const(temperature_below_enable());
         mock.expect_fan_is_enabled().return_const(false);
 
         // THEN:
         for _ in 0..=1000 {
-            should_not_exit(&mut mock, &mut seq);
+            keep_running(&mut mock, &mut seq, &ctx);
         }
 
-        should_exit(&mut mock, &mut seq);
+        stop_after_sleep(&mut mock, &mut seq, &ctx);
 
         // WHEN:
-        main_loop(&c, &mut mock);
+        main_loop(&c, &mut mock, &ctx.token);
     }
 
     #[test]
     fn fan_does_not_disable_when_above_temperature() {
-        let (c, mut seq, mut mock) = test_setup();
+        let (c, mut seq, mut mock, ctx) = test_setup();
 
         // GIVEN:
-        mock.expect_sleep().return_const(());
         mock.expect_get_cpu_temperature()
             .return_const(temperature_above_disable());
         mock.expect_fan_is_enabled().return_const(true);
 
         // THEN:
-        should_not_exit(&mut mock, &mut seq);
+        keep_running(&mut mock, &mut seq, &ctx);
 
-        should_exit(&mut mock, &mut seq);
+        stop_after_sleep(&mut mock, &mut seq, &ctx);
 
         // WHEN:
-        main_loop(&c, &mut mock);
+        main_loop(&c, &mut mock, &ctx.token);
     }
 
     #[test]
     fn fan_enables_after_long_below_temperature() {
-        let (c, mut seq, mut mock) = test_setup();
+        let (c, mut seq, mut mock, ctx) = test_setup();
 
         // GIVEN:
-        mock.expect_sleep().return_const(());
-        let (r, g, b) = c.led_on_color;
+        let Color {
+            red: r,
+            green: g,
+            blue: b,
+        } = c.led_on_color;
         mock.expect_set_led_rgb_brightness()
             .with(eq(r), eq(g), eq(b), eq(c.led_brightness))
             .return_const(());
@@ -373,7 +1169,7 @@ mod test {
                 .times(1)
                 .in_sequence(&mut seq)
                 .return_const(false);
-            should_not_exit(&mut mock, &mut seq);
+            keep_running(&mut mock, &mut seq, &ctx);
         }
 
         // Then we go above
@@ -386,7 +1182,7 @@ mod test {
                 .times(1)
                 .in_sequence(&mut seq)
                 .return_const(false);
-            should_not_exit(&mut mock, &mut seq);
+            keep_running(&mut mock, &mut seq, &ctx);
         }
         mock.expect_get_cpu_temperature()
             .times(1)
@@ -402,19 +1198,22 @@ mod test {
             .in_sequence(&mut seq)
             .return_const(());
 
-        should_exit(&mut mock, &mut seq);
+        stop_after_sleep(&mut mock, &mut seq, &ctx);
 
         // WHEN:
-        main_loop(&c, &mut mock);
+        main_loop(&c, &mut mock, &ctx.token);
     }
 
     #[test]
     fn fan_disables_after_long_above_temperature() {
-        let (c, mut seq, mut mock) = test_setup();
+        let (c, mut seq, mut mock, ctx) = test_setup();
 
         // GIVEN:
-        mock.expect_sleep().return_const(());
-        let (r, g, b) = c.led_off_color;
+        let Color {
+            red: r,
+            green: g,
+            blue: b,
+        } = c.led_off_color;
         mock.expect_set_led_rgb_brightness()
             .with(eq(r), eq(g), eq(b), eq(c.led_brightness))
             .return_const(());
@@ -430,7 +1229,7 @@ mod test {
                 .times(1)
                 .in_sequence(&mut seq)
                 .return_const(true);
-            should_not_exit(&mut mock, &mut seq);
+            keep_running(&mut mock, &mut seq, &ctx);
         }
 
         // Then we go above
@@ -443,7 +1242,7 @@ mod test {
                 .times(1)
                 .in_sequence(&mut seq)
                 .return_const(true);
-            should_not_exit(&mut mock, &mut seq);
+            keep_running(&mut mock, &mut seq, &ctx);
         }
         mock.expect_get_cpu_temperature()
             .times(1)
@@ -459,18 +1258,15 @@ mod test {
             .in_sequence(&mut seq)
             .return_const(());
 
-        should_exit(&mut mock, &mut seq);
+        stop_after_sleep(&mut mock, &mut seq, &ctx);
 
         // WHEN:
-        main_loop(&c, &mut mock);
+        main_loop(&c, &mut mock, &ctx.token);
     }
 
     #[test]
     fn fan_does_not_disable_when_temperature_fluctuating() {
-        let (c, mut seq, mut mock) = test_setup();
-
-        // GIVEN:
-        mock.expect_sleep().return_const(());
+        let (c, mut seq, mut mock, ctx) = test_setup();
 
         // THEN:
         for _ in 0..=1000 {
@@ -482,7 +1278,7 @@ mod test {
                 .times(1)
                 .in_sequence(&mut seq)
                 .return_const(true);
-            should_not_exit(&mut mock, &mut seq);
+            keep_running(&mut mock, &mut seq, &ctx);
 
             mock.expect_get_cpu_temperature()
                 .times(1)
@@ -492,7 +1288,7 @@ mod test {
                 .times(1)
                 .in_sequence(&mut seq)
                 .return_const(true);
-            should_not_exit(&mut mock, &mut seq);
+            keep_running(&mut mock, &mut seq, &ctx);
         }
 
         mock.expect_get_cpu_temperature()
@@ -503,18 +1299,15 @@ mod test {
             .times(1)
             .in_sequence(&mut seq)
             .return_const(true);
-        should_exit(&mut mock, &mut seq);
+        stop_after_sleep(&mut mock, &mut seq, &ctx);
 
         // WHEN:
-        main_loop(&c, &mut mock);
+        main_loop(&c, &mut mock, &ctx.token);
     }
 
     #[test]
     fn fan_does_not_enable_when_temperature_fluctuating() {
-        let (c, mut seq, mut mock) = test_setup();
-
-        // GIVEN:
-        mock.expect_sleep().return_const(());
+        let (c, mut seq, mut mock, ctx) = test_setup();
 
         // THEN:
         for _ in 0..=1000 {
@@ -526,7 +1319,7 @@ mod test {
                 .times(1)
                 .in_sequence(&mut seq)
                 .return_const(false);
-            should_not_exit(&mut mock, &mut seq);
+            keep_running(&mut mock, &mut seq, &ctx);
 
             mock.expect_get_cpu_temperature()
                 .times(1)
@@ -536,7 +1329,7 @@ mod test {
                 .times(1)
                 .in_sequence(&mut seq)
                 .return_const(false);
-            should_not_exit(&mut mock, &mut seq);
+            keep_running(&mut mock, &mut seq, &ctx);
         }
 
         mock.expect_get_cpu_temperature()
@@ -547,9 +1340,777 @@ mod test {
             .times(1)
             .in_sequence(&mut seq)
             .return_const(false);
-        should_exit(&mut mock, &mut seq);
+        stop_after_sleep(&mut mock, &mut seq, &ctx);
+
+        // WHEN:
+        main_loop(&c, &mut mock, &ctx.token);
+    }
+
+    fn expect_temperature(
+        mock: &mut super::MockShimLayer,
+        seq: &mut Sequence,
+        temperature: MilliCelsius,
+    ) {
+        mock.expect_get_cpu_temperature()
+            .times(1)
+            .in_sequence(seq)
+            .return_const(temperature);
+    }
+
+    #[test]
+    fn median_filter_ignores_single_spike() {
+        let (mut c, mut seq, mut mock, ctx) = test_setup();
+        c.number_of_readings_before_action = 1;
+        c.filter = FilterConfig::Median { window: 3 };
+
+        // GIVEN:
+        mock.expect_fan_is_enabled().return_const(false);
+
+        // THEN:
+        for temperature in [
+            temperature_below_enable(),
+            temperature_below_enable(),
+            temperature_above_enable(),
+            temperature_below_enable(),
+            temperature_below_enable(),
+        ] {
+            expect_temperature(&mut mock, &mut seq, temperature);
+            keep_running(&mut mock, &mut seq, &ctx);
+        }
+        expect_temperature(&mut mock, &mut seq, temperature_below_enable());
+        stop_after_sleep(&mut mock, &mut seq, &ctx);
+
+        mock.expect_turn_fan_on().never();
+
+        // WHEN:
+        main_loop(&c, &mut mock, &ctx.token);
+    }
+
+    #[test]
+    fn median_filter_enables_on_sustained_rise() {
+        let (mut c, mut seq, mut mock, ctx) = test_setup();
+        c.number_of_readings_before_action = 1;
+        c.filter = FilterConfig::Median { window: 3 };
+
+        // GIVEN:
+        mock.expect_fan_is_enabled().return_const(false);
+        mock.expect_set_led_rgb_brightness().return_const(());
+
+        // THEN:
+        expect_temperature(&mut mock, &mut seq, temperature_below_enable());
+        keep_running(&mut mock, &mut seq, &ctx);
+        expect_temperature(&mut mock, &mut seq, temperature_above_enable());
+        keep_running(&mut mock, &mut seq, &ctx);
+        expect_temperature(&mut mock, &mut seq, temperature_above_enable());
+
+        mock.expect_turn_fan_on()
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(());
+
+        stop_after_sleep(&mut mock, &mut seq, &ctx);
+
+        // WHEN:
+        main_loop(&c, &mut mock, &ctx.token);
+    }
+
+    #[test]
+    fn moving_average_filter_enables_despite_noisy_dip() {
+        let (mut c, mut seq, mut mock, ctx) = test_setup();
+        c.filter = FilterConfig::MovingAverage { window: 3 };
+
+        // GIVEN:
+        mock.expect_fan_is_enabled().return_const(false);
+        mock.expect_set_led_rgb_brightness().return_const(());
+        let hot = MilliCelsius(c.enable_fan_at_temperature.0 + 5_000);
+        let dip = MilliCelsius(c.enable_fan_at_temperature.0 - 1_000);
+
+        // THEN:
+        // Counting raw readings would start over after the dip.
+        for temperature in [hot, dip] {
+            expect_temperature(&mut mock, &mut seq, temperature);
+            keep_running(&mut mock, &mut seq, &ctx);
+        }
+        expect_temperature(&mut mock, &mut seq, hot);
+
+        mock.expect_turn_fan_on()
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(());
+
+        stop_after_sleep(&mut mock, &mut seq, &ctx);
+
+        // WHEN:
+        main_loop(&c, &mut mock, &ctx.token);
+    }
+
+    fn expect_reading(
+        mock: &mut super::MockShimLayer,
+        seq: &mut Sequence,
+        temperature: MilliCelsius,
+        fan_enabled: bool,
+    ) {
+        expect_temperature(mock, seq, temperature);
+        mock.expect_fan_is_enabled()
+            .times(1)
+            .in_sequence(seq)
+            .return_const(fan_enabled);
+    }
+
+    #[test]
+    fn fan_stays_on_for_min_on_time() {
+        let (mut c, mut seq, mut mock, ctx) = test_setup();
+        c.number_of_readings_before_action = 1;
+        c.delay_between_readings = Duration::from_secs(10);
+        c.min_on_time = Duration::from_secs(60);
+
+        // GIVEN:
+        mock.expect_set_led_rgb_brightness().return_const(());
+
+        // THEN:
+        expect_reading(&mut mock, &mut seq, temperature_above_enable(), false);
+        mock.expect_turn_fan_on()
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(());
+        keep_running(&mut mock, &mut seq, &ctx);
+
+        // Cold straight away, but the fan has to stay on for a minute.
+        for _ in 0..5 {
+            expect_reading(&mut mock, &mut seq, temperature_below_disable(), true);
+            keep_running(&mut mock, &mut seq, &ctx);
+        }
+        expect_reading(&mut mock, &mut seq, temperature_below_disable(), true);
+        mock.expect_turn_fan_off()
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(());
+        stop_after_sleep(&mut mock, &mut seq, &ctx);
+
+        // WHEN:
+        main_loop(&c, &mut mock, &ctx.token);
+    }
+
+    #[test]
+    fn fan_stays_off_for_min_off_time() {
+        let (mut c, mut seq, mut mock, ctx) = test_setup();
+        c.number_of_readings_before_action = 1;
+        c.delay_between_readings = Duration::from_secs(10);
+        c.min_off_time = Duration::from_secs(30);
+
+        // GIVEN:
+        mock.expect_set_led_rgb_brightness().return_const(());
+
+        // THEN:
+        expect_reading(&mut mock, &mut seq, temperature_below_disable(), true);
+        mock.expect_turn_fan_off()
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(());
+        keep_running(&mut mock, &mut seq, &ctx);
+
+        for _ in 0..2 {
+            expect_reading(&mut mock, &mut seq, temperature_above_enable(), false);
+            keep_running(&mut mock, &mut seq, &ctx);
+        }
+        expect_reading(&mut mock, &mut seq, temperature_above_enable(), false);
+        mock.expect_turn_fan_on()
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(());
+        stop_after_sleep(&mut mock, &mut seq, &ctx);
+
+        // WHEN:
+        main_loop(&c, &mut mock, &ctx.token);
+    }
+
+    #[test]
+    fn fan_changes_are_limited_per_hour() {
+        let (mut c, mut seq, mut mock, ctx) = test_setup();
+        c.number_of_readings_before_action = 1;
+        c.delay_between_readings = Duration::from_secs(60);
+        c.max_changes_per_hour = 2;
+
+        // GIVEN:
+        mock.expect_set_led_rgb_brightness().return_const(());
+
+        // THEN:
+        expect_reading(&mut mock, &mut seq, temperature_above_enable(), false);
+        mock.expect_turn_fan_on()
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(());
+        keep_running(&mut mock, &mut seq, &ctx);
+        expect_reading(&mut mock, &mut seq, temperature_below_disable(), true);
+        mock.expect_turn_fan_off()
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(());
+        keep_running(&mut mock, &mut seq, &ctx);
+
+        // The third change has to wait until the first is an hour old.
+        for _ in 2..60 {
+            expect_reading(&mut mock, &mut seq, temperature_above_enable(), false);
+            keep_running(&mut mock, &mut seq, &ctx);
+        }
+        expect_reading(&mut mock, &mut seq, temperature_above_enable(), false);
+        mock.expect_turn_fan_on()
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(());
+        stop_after_sleep(&mut mock, &mut seq, &ctx);
+
+        // WHEN:
+        main_loop(&c, &mut mock, &ctx.token);
+    }
+
+    #[test]
+    fn fan_is_kick_started_before_low_speed() {
+        let (mut c, mut seq, mut mock, ctx) = test_setup();
+        c.number_of_readings_before_action = 1;
+        c.max_fan_speed = 0.4;
+        c.kick_start_time = Duration::from_millis(500);
+
+        // GIVEN:
+        mock.expect_set_led_rgb_brightness().return_const(());
+
+        // THEN:
+        expect_reading(&mut mock, &mut seq, temperature_above_enable(), false);
+        mock.expect_turn_fan_on()
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(());
+        mock.expect_sleep()
+            .with(eq(Duration::from_millis(500)))
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(());
+        mock.expect_set_fan_duty()
+            .with(eq(0.4))
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(());
+        keep_running(&mut mock, &mut seq, &ctx);
+
+        // Already spinning, so no kick.
+        expect_reading(&mut mock, &mut seq, temperature_above_enable(), true);
+        stop_after_sleep(&mut mock, &mut seq, &ctx);
+
+        // WHEN:
+        main_loop(&c, &mut mock, &ctx.token);
+    }
+
+    #[test]
+    fn fan_stays_off_below_min_speed() {
+        let (mut c, mut seq, mut mock, ctx) = test_setup();
+        c.number_of_readings_before_action = 1;
+        c.max_fan_speed = 0.2;
+        c.min_fan_speed = 0.3;
+
+        // THEN:
+        mock.expect_turn_fan_on().never();
+        mock.expect_set_fan_duty().never();
+        expect_reading(&mut mock, &mut seq, temperature_above_enable(), false);
+        keep_running(&mut mock, &mut seq, &ctx);
+        expect_reading(&mut mock, &mut seq, temperature_above_enable(), false);
+        stop_after_sleep(&mut mock, &mut seq, &ctx);
+
+        // WHEN:
+        main_loop(&c, &mut mock, &ctx.token);
+    }
+
+    #[test]
+    fn fan_ramps_up_over_several_readings() {
+        let (mut c, mut seq, mut mock, ctx) = test_setup();
+        c.number_of_readings_before_action = 1;
+        c.delay_between_readings = Duration::from_secs(1);
+        c.ramp_up_per_second = 0.25;
+
+        // GIVEN:
+        mock.expect_set_led_rgb_brightness().return_const(());
+
+        // THEN:
+        expect_reading(&mut mock, &mut seq, temperature_above_enable(), false);
+        for (i, duty) in [0.25, 0.5, 0.75].iter().enumerate() {
+            if i > 0 {
+                expect_reading(&mut mock, &mut seq, temperature_above_enable(), true);
+            }
+            mock.expect_set_fan_duty()
+                .with(eq(*duty))
+                .times(1)
+                .in_sequence(&mut seq)
+                .return_const(());
+            keep_running(&mut mock, &mut seq, &ctx);
+        }
+        expect_reading(&mut mock, &mut seq, temperature_above_enable(), true);
+        mock.expect_turn_fan_on()
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(());
+        stop_after_sleep(&mut mock, &mut seq, &ctx);
+
+        // WHEN:
+        main_loop(&c, &mut mock, &ctx.token);
+    }
+
+    #[test]
+    fn status_line_shows_ramp() {
+        let mut ramp = Ramp::new(&FanshimConfig {
+            ramp_up_per_second: 0.25,
+            ..FanshimConfig::default()
+        });
+        ramp.set_target(1.0);
+        ramp.step(Duration::from_secs(2));
+
+        assert_eq!(
+            status_line(
+                MilliCelsius(66_000),
+                &ramp,
+                Health::Ok,
+                Throttled(0x50005),
+                Some("silent")
+            ),
+            "Temperature 66.0°C, fan at 50%, ramping up to on, under-voltage, throttled, profile silent"
+        );
+    }
+
+    #[test]
+    fn throttle_conditions_map_to_led_alert_colours() {
+        let mut c = FanshimConfig::default();
+        assert_eq!(alert_color(&c, Throttled(0x50005)), None);
+
+        c.throttle.under_voltage_led_color = Some(Color::rgb(255, 255, 0));
+        c.throttle.throttled_led_color = Some(Color::rgb(255, 128, 0));
+        assert_eq!(
+            alert_color(&c, Throttled(0x50005)),
+            Some(Color::rgb(255, 255, 0))
+        );
+        assert_eq!(
+            alert_color(&c, Throttled(0x8)),
+            Some(Color::rgb(255, 128, 0))
+        );
+        // Only recorded since boot, nothing active now.
+        assert_eq!(alert_color(&c, Throttled(0x50000)), None);
+    }
+
+    #[test]
+    fn fan_ramps_down_over_several_readings() {
+        let (mut c, mut seq, mut mock, ctx) = test_setup();
+        c.number_of_readings_before_action = 1;
+        c.delay_between_readings = Duration::from_secs(1);
+        c.ramp_down_per_second = 0.5;
+
+        // GIVEN:
+        mock.expect_set_led_rgb_brightness().return_const(());
+
+        // THEN:
+        expect_reading(&mut mock, &mut seq, temperature_below_disable(), true);
+        mock.expect_set_fan_duty()
+            .with(eq(0.5))
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(());
+        keep_running(&mut mock, &mut seq, &ctx);
+        // Still cold, but the fan is already on its way down.
+        expect_reading(&mut mock, &mut seq, temperature_below_disable(), true);
+        mock.expect_turn_fan_off()
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(());
+        stop_after_sleep(&mut mock, &mut seq, &ctx);
+
+        // WHEN:
+        main_loop(&c, &mut mock, &ctx.token);
+    }
+
+    #[test]
+    fn fan_turns_on_early_when_load_jumps() {
+        let (mut c, mut seq, mut mock, ctx) = test_setup();
+        c.preemptive_load_increase = 0.5;
+        ctx.loads.lock().unwrap().extend(&[0.1, 0.2, 0.9]);
+
+        // GIVEN:
+        mock.expect_set_led_rgb_brightness().return_const(());
+
+        // THEN:
+        // Between the thresholds, so only the jump in load turns the fan on.
+        for _ in 0..2 {
+            expect_reading(&mut mock, &mut seq, temperature_above_disable(), false);
+            keep_running(&mut mock, &mut seq, &ctx);
+        }
+        expect_reading(&mut mock, &mut seq, temperature_above_disable(), false);
+        mock.expect_turn_fan_on()
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(());
+        stop_after_sleep(&mut mock, &mut seq, &ctx);
+
+        // WHEN:
+        main_loop(&c, &mut mock, &ctx.token);
+    }
+
+    #[test]
+    fn led_turns_off_when_idle_and_wakes_on_button_press() {
+        let (mut c, mut seq, mut mock, ctx) = test_setup();
+        c.delay_between_readings = Duration::from_secs(10);
+        c.led_off_after_idle = Duration::from_secs(30);
+        ctx.presses
+            .lock()
+            .unwrap()
+            .extend(vec![false, false, false, false, true]);
+
+        // GIVEN:
+        mock.expect_get_cpu_temperature()
+            .return_const(temperature_below_disable());
+        mock.expect_fan_is_enabled().return_const(false);
+
+        // THEN:
+        for _ in 0..3 {
+            keep_running(&mut mock, &mut seq, &ctx);
+        }
+        mock.expect_set_led_rgb_brightness()
+            .with(eq(0), eq(0), eq(0), eq(0.0))
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(());
+        keep_running(&mut mock, &mut seq, &ctx);
+        let Color {
+            red: r,
+            green: g,
+            blue: b,
+        } = c.led_off_color;
+        mock.expect_set_led_rgb_brightness()
+            .with(eq(r), eq(g), eq(b), eq(c.led_brightness))
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(());
+        stop_after_sleep(&mut mock, &mut seq, &ctx);
+
+        // WHEN:
+        main_loop(&c, &mut mock, &ctx.token);
+    }
+
+    #[test]
+    fn status_shows_on_led_until_it_clears() {
+        let (c, mut seq, mut mock, ctx) = test_setup();
+        let network_down = Indication {
+            name: "network down".to_owned(),
+            color: Color::rgb(0, 0, 255),
+            pattern: Pattern::Solid,
+        };
+        ctx.statuses.lock().unwrap().extend(vec![
+            Some(network_down.clone()),
+            Some(network_down),
+            None,
+        ]);
+
+        // GIVEN:
+        mock.expect_get_cpu_temperature()
+            .return_const(temperature_below_disable());
+        mock.expect_fan_is_enabled().return_const(false);
+
+        // THEN:
+        mock.expect_set_led_rgb_brightness()
+            .with(eq(0), eq(0), eq(255), eq(c.led_brightness))
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(());
+        keep_running(&mut mock, &mut seq, &ctx);
+        keep_running(&mut mock, &mut seq, &ctx);
+        let Color {
+            red: r,
+            green: g,
+            blue: b,
+        } = c.led_off_color;
+        mock.expect_set_led_rgb_brightness()
+            .with(eq(r), eq(g), eq(b), eq(c.led_brightness))
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(());
+        stop_after_sleep(&mut mock, &mut seq, &ctx);
+
+        // WHEN:
+        main_loop(&c, &mut mock, &ctx.token);
+    }
+
+    #[test]
+    fn hooks_run_on_fan_and_alarm_changes() {
+        let (mut c, mut seq, mut mock, ctx) = test_setup();
+        c.number_of_readings_before_action = 1;
+        let network_down = Indication {
+            name: "network down".to_owned(),
+            color: Color::rgb(0, 0, 255),
+            pattern: Pattern::Solid,
+        };
+        ctx.statuses
+            .lock()
+            .unwrap()
+            .extend(vec![Some(network_down), None]);
+
+        // GIVEN:
+        mock.expect_turn_fan_on().return_const(());
+        mock.expect_set_led_rgb_brightness().return_const(());
 
         // WHEN:
-        main_loop(&c, &mut mock);
+        expect_reading(&mut mock, &mut seq, temperature_above_enable(), false);
+        keep_running(&mut mock, &mut seq, &ctx);
+        expect_reading(&mut mock, &mut seq, temperature_above_enable(), true);
+        stop_after_sleep(&mut mock, &mut seq, &ctx);
+        main_loop(&c, &mut mock, &ctx.token);
+
+        // THEN:
+        assert_eq!(
+            *ctx.hooks.lock().unwrap(),
+            vec![Event::Alarm, Event::FanOn, Event::AlarmClear]
+        );
+    }
+
+    #[test]
+    fn schedule_overrides_threshold_and_led_brightness() {
+        let (mut c, mut seq, mut mock, ctx) = test_setup();
+        c.number_of_readings_before_action = 1;
+        c.delay_between_readings = Duration::from_secs(10);
+        c.schedule = toml::from_str::<FanshimInterimConfig>(
+            r#"
+            [[schedule]]
+            start = "23:00"
+            end = "23:01"
+            enable_fan_at_degrees_celsius = 70
+            led_brightness = 0.0
+            "#,
+        )
+        .unwrap()
+        .schedule;
+        *ctx.clock.lock().unwrap() =
+            VirtualClock::new(SystemTime::from(Local.ymd(2020, 10, 2).and_hms(23, 0, 0)));
+
+        // GIVEN:
+        mock.expect_turn_fan_off().return_const(());
+        mock.expect_set_led_rgb_brightness()
+            .with(always(), always(), always(), eq(c.led_brightness))
+            .return_const(());
+
+        // THEN:
+        // The LED is turned off when the window starts.
+        mock.expect_set_led_rgb_brightness()
+            .with(always(), always(), always(), eq(0.0))
+            .times(1)
+            .return_const(());
+
+        for _ in 0..6 {
+            expect_reading(&mut mock, &mut seq, temperature_above_enable(), false);
+            keep_running(&mut mock, &mut seq, &ctx);
+        }
+        expect_reading(&mut mock, &mut seq, temperature_above_enable(), false);
+        mock.expect_turn_fan_on()
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(());
+        stop_after_sleep(&mut mock, &mut seq, &ctx);
+
+        // WHEN:
+        main_loop(&c, &mut mock, &ctx.token);
+    }
+
+    #[test]
+    fn tick_takes_one_reading_without_sleeping() {
+        let (mut c, mut seq, mut mock, _) = test_setup();
+        c.number_of_readings_before_action = 1;
+
+        // GIVEN:
+        mock.expect_set_led_rgb_brightness().return_const(());
+        expect_reading(&mut mock, &mut seq, temperature_above_enable(), false);
+
+        // THEN:
+        mock.expect_turn_fan_on().times(1).return_const(());
+        mock.expect_sleep().never();
+
+        // WHEN:
+        Controller::new(&c).tick(&mut mock);
+    }
+
+    #[test]
+    fn tick_leaves_the_kick_start_wait_to_the_caller() {
+        let (mut c, mut seq, mut mock, _) = test_setup();
+        c.number_of_readings_before_action = 1;
+        c.max_fan_speed = 0.4;
+        c.kick_start_time = Duration::from_millis(500);
+        let mut controller = Controller::new(&c);
+
+        // GIVEN:
+        mock.expect_set_led_rgb_brightness().return_const(());
+        expect_reading(&mut mock, &mut seq, temperature_above_enable(), false);
+
+        // THEN:
+        mock.expect_turn_fan_on()
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(());
+        mock.expect_set_fan_duty()
+            .with(eq(0.4))
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(());
+        mock.expect_sleep().never();
+
+        // WHEN:
+        assert_eq!(controller.tick(&mut mock), Some(c.kick_start_time));
+        controller.end_kick_start(&mut mock);
+    }
+
+    #[test]
+    fn subscribers_follow_readings_and_decisions() {
+        let (mut c, mut seq, mut mock, _) = test_setup();
+        c.number_of_readings_before_action = 1;
+        let mut controller = Controller::new(&c);
+        let events = controller.subscribe();
+
+        // GIVEN:
+        mock.expect_set_led_rgb_brightness().return_const(());
+        mock.expect_turn_fan_on().return_const(());
+        expect_reading(&mut mock, &mut seq, temperature_below_enable(), false);
+        expect_reading(&mut mock, &mut seq, temperature_above_enable(), false);
+
+        // WHEN:
+        controller.tick(&mut mock);
+        events.try_iter().for_each(drop);
+        controller.tick(&mut mock);
+        controller.reload(&c);
+
+        // THEN:
+        assert_eq!(
+            events.try_iter().collect::<Vec<_>>(),
+            vec![
+                observer::Event::TemperatureRead {
+                    raw: temperature_above_enable(),
+                    filtered: temperature_above_enable(),
+                },
+                observer::Event::ThresholdCrossed {
+                    threshold: observer::Threshold::Enable,
+                    above: true,
+                    temperature: temperature_above_enable(),
+                },
+                observer::Event::FanStateChanged {
+                    enabled: true,
+                    duty: c.max_fan_speed,
+                },
+                observer::Event::LedChanged {
+                    color: c.led_on_color,
+                    brightness: c.led_brightness,
+                },
+                observer::Event::ConfigReloaded { profile: None },
+            ]
+        );
+    }
+
+    #[test]
+    fn reload_keeps_the_fan_running_and_the_guard() {
+        let (mut c, mut seq, mut mock, _) = test_setup();
+        c.number_of_readings_before_action = 1;
+        c.min_on_time = Duration::from_secs(60);
+        let mut controller = Controller::new(&c);
+        let events = controller.subscribe();
+
+        // GIVEN:
+        mock.expect_set_led_rgb_brightness().return_const(());
+        expect_reading(&mut mock, &mut seq, temperature_above_enable(), false);
+        // Between the thresholds, then cold within the minimum on time.
+        expect_reading(&mut mock, &mut seq, temperature_above_disable(), true);
+        expect_reading(&mut mock, &mut seq, temperature_below_disable(), true);
+
+        // THEN:
+        mock.expect_turn_fan_on().times(1).return_const(());
+        mock.expect_turn_fan_off().never();
+
+        // WHEN:
+        controller.tick(&mut mock);
+        let reloaded = FanshimConfig {
+            led_brightness: 0.5,
+            ..c.clone()
+        };
+        controller.reload(&reloaded);
+        controller.tick(&mut mock);
+        controller.tick(&mut mock);
+
+        assert!(events.try_iter().any(|e| e
+            == observer::Event::LedChanged {
+                color: c.led_on_color,
+                brightness: 0.5,
+            }));
+    }
+
+    #[cfg(feature = "async")]
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        use std::task::{Context, Poll, Wake, Waker};
+        use std::thread::{self, Thread};
+
+        struct Unpark(Thread);
+        impl Wake for Unpark {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+
+        let waker = Waker::from(Arc::new(Unpark(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut future = std::pin::pin!(future);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+            thread::park();
+        }
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn run_waits_between_readings_until_shutdown() {
+        use crate::async_shim::Timed;
+        use std::future::{poll_fn, ready};
+        use std::task::Poll;
+
+        let (mut c, mut seq, mut mock, _) = test_setup();
+        c.number_of_readings_before_action = 1;
+        c.max_fan_speed = 0.4;
+        c.kick_start_time = Duration::from_millis(500);
+        let waits = Arc::new(Mutex::new(Vec::new()));
+
+        // GIVEN:
+        mock.expect_set_led_rgb_brightness().return_const(());
+        expect_reading(&mut mock, &mut seq, temperature_above_enable(), false);
+        expect_reading(&mut mock, &mut seq, temperature_above_enable(), true);
+
+        // THEN:
+        mock.expect_turn_fan_on().times(1).return_const(());
+        mock.expect_set_fan_duty()
+            .with(eq(0.4))
+            .times(1)
+            .return_const(());
+        mock.expect_sleep().never();
+
+        // WHEN:
+        let recorded = Arc::clone(&waits);
+        let mut shim = Timed::new(mock, move |dur| {
+            recorded.lock().unwrap().push(dur);
+            ready(())
+        });
+        let recorded = Arc::clone(&waits);
+        let shutdown = poll_fn(move |_| {
+            if recorded.lock().unwrap().len() >= 3 {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        });
+        block_on(Controller::new(&c).run(&mut shim, shutdown));
+
+        assert_eq!(
+            *waits.lock().unwrap(),
+            vec![
+                c.kick_start_time,
+                c.delay_between_readings,
+                c.delay_between_readings
+            ]
+        );
     }
 }
@@ -0,0 +1,290 @@
+use crate::config::FanshimConfig;
+use log::warn;
+use std::env;
+use std::ffi::OsString;
+use std::io;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::path::Path;
+use std::process;
+use std::time::Duration;
+
+const NOTIFY_SOCKET: &str = "NOTIFY_SOCKET";
+const WATCHDOG_USEC: &str = "WATCHDOG_USEC";
+const WATCHDOG_PID: &str = "WATCHDOG_PID";
+
+/// A state change reported to the service manager, see `man sd_notify`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NotifyState {
+    Ready,
+    Reloading,
+    Stopping,
+    Status(String),
+    Watchdog,
+}
+
+impl NotifyState {
+    fn message(&self) -> String {
+        match self {
+            NotifyState::Ready => "READY=1".to_owned(),
+            NotifyState::Reloading => "RELOADING=1".to_owned(),
+            NotifyState::Stopping => "STOPPING=1".to_owned(),
+            NotifyState::Status(s) => format!("STATUS={}", s.replace('\n', " ")),
+            NotifyState::Watchdog => "WATCHDOG=1".to_owned(),
+        }
+    }
+}
+
+/// Sends `sd_notify` messages to systemd.
+///
+/// Every method is a no-op when the daemon was not started with `NOTIFY_SOCKET` set,
+/// e.g. when it is run by hand or by a `Type=simple` unit.
+#[derive(Debug)]
+pub struct Notifier {
+    socket: Option<(UnixDatagram, SocketAddr)>,
+    watchdog_interval: Option<Duration>,
+}
+
+impl Notifier {
+    /// Reads `NOTIFY_SOCKET`, `WATCHDOG_USEC` and `WATCHDOG_PID` from the environment.
+    pub fn from_env() -> Self {
+        Self::from_vars(
+            env::var_os(NOTIFY_SOCKET),
+            env::var(WATCHDOG_USEC).ok(),
+            env::var(WATCHDOG_PID).ok(),
+        )
+    }
+
+    /// A notifier that never sends anything.
+    pub fn disabled() -> Self {
+        Self {
+            socket: None,
+            watchdog_interval: None,
+        }
+    }
+
+    /// Sends notifications to the datagram socket at `path`.
+    pub fn with_socket_path(path: &Path, watchdog_interval: Option<Duration>) -> io::Result<Self> {
+        Ok(Self {
+            socket: Some((UnixDatagram::unbound()?, SocketAddr::from_pathname(path)?)),
+            watchdog_interval,
+        })
+    }
+
+    fn from_vars(
+        notify_socket: Option<OsString>,
+        watchdog_usec: Option<String>,
+        watchdog_pid: Option<String>,
+    ) -> Self {
+        let socket = notify_socket.and_then(|s| match connect(&s) {
+            Ok(socket) => Some(socket),
+            Err(e) => {
+                warn!("Unable to use {} {:?}: {}", NOTIFY_SOCKET, s, e);
+                None
+            }
+        });
+
+        let watchdog_is_ours = match watchdog_pid {
+            Some(pid) => pid.trim().parse::<u32>().ok() == Some(process::id()),
+            None => true,
+        };
+        let watchdog_interval = watchdog_usec
+            .and_then(|usec| usec.trim().parse::<u64>().ok())
+            .filter(|&usec| usec > 0 && watchdog_is_ours)
+            .map(Duration::from_micros);
+
+        Self {
+            socket,
+            watchdog_interval,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.socket.is_some()
+    }
+
+    /// How often systemd expects a `WATCHDOG=1` ping, if the watchdog is enabled.
+    pub fn watchdog_interval(&self) -> Option<Duration> {
+        self.watchdog_interval
+    }
+
+    pub fn notify(&self, state: &NotifyState) -> io::Result<()> {
+        let (socket, addr) = match &self.socket {
+            Some(s) => s,
+            None => return Ok(()),
+        };
+
+        if *state == NotifyState::Watchdog && self.watchdog_interval.is_none() {
+            return Ok(());
+        }
+
+        socket.send_to_addr(state.message().as_bytes(), addr)?;
+        Ok(())
+    }
+}
+
+/// Shortens the delay between readings so the watchdog is pinged at least twice per `interval`,
+/// warning if it has to. A reading that kick-starts the fan takes the kick-start time longer.
+pub fn fit_watchdog(config: &mut FanshimConfig, interval: Option<Duration>) {
+    let limit = match interval {
+        Some(interval) => interval / 2,
+        None => return,
+    };
+    let period = config.delay_between_readings + config.kick_start_time;
+    if period <= limit {
+        return;
+    }
+    if config.kick_start_time >= limit {
+        warn!(
+            "The kick-start time of {:?} exceeds half the watchdog interval of {:?}. \
+             systemd may restart the driver while the fan starts",
+            config.kick_start_time,
+            limit * 2
+        );
+        return;
+    }
+    let delay = limit - config.kick_start_time;
+    warn!(
+        "Readings every {:?} are too far apart for the watchdog interval of {:?}. \
+         Taking them every {:?} instead",
+        period,
+        limit * 2,
+        delay
+    );
+    config.delay_between_readings = delay;
+}
+
+fn connect(notify_socket: &OsString) -> io::Result<(UnixDatagram, SocketAddr)> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let bytes = notify_socket.as_bytes();
+    let addr = match bytes.first() {
+        Some(b'@') => {
+            use std::os::linux::net::SocketAddrExt;
+            SocketAddr::from_abstract_name(&bytes[1..])?
+        }
+        Some(b'/') => SocketAddr::from_pathname(Path::new(notify_socket))?,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "socket must be an absolute path or an abstract address",
+            ))
+        }
+    };
+
+    Ok((UnixDatagram::unbound()?, addr))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{fit_watchdog, Notifier, NotifyState};
+    use crate::config::FanshimConfig;
+    use std::os::unix::net::UnixDatagram;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    struct FakeSocket {
+        path: PathBuf,
+        socket: UnixDatagram,
+    }
+
+    impl FakeSocket {
+        fn bind(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "fanshim-notify-{}-{}.sock",
+                std::process::id(),
+                name
+            ));
+            let _ = std::fs::remove_file(&path);
+            let socket = UnixDatagram::bind(&path).unwrap();
+            socket.set_nonblocking(true).unwrap();
+            Self { path, socket }
+        }
+
+        fn receive(&self) -> Option<String> {
+            let mut buf = [0; 256];
+            self.socket
+                .recv(&mut buf)
+                .ok()
+                .map(|n| String::from_utf8_lossy(&buf[..n]).into_owned())
+        }
+    }
+
+    impl Drop for FakeSocket {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+
+    #[test]
+    fn sends_messages_to_socket() {
+        let fake = FakeSocket::bind("messages");
+        let n = Notifier::with_socket_path(&fake.path, Some(Duration::from_secs(1))).unwrap();
+
+        n.notify(&NotifyState::Ready).unwrap();
+        n.notify(&NotifyState::Status("Fan on\nLED red".to_owned()))
+            .unwrap();
+        n.notify(&NotifyState::Watchdog).unwrap();
+        n.notify(&NotifyState::Reloading).unwrap();
+
+        assert_eq!(fake.receive().as_deref(), Some("READY=1"));
+        assert_eq!(fake.receive().as_deref(), Some("STATUS=Fan on LED red"));
+        assert_eq!(fake.receive().as_deref(), Some("WATCHDOG=1"));
+        assert_eq!(fake.receive().as_deref(), Some("RELOADING=1"));
+        assert_eq!(fake.receive(), None);
+    }
+
+    #[test]
+    fn no_op_without_notify_socket() {
+        let n = Notifier::from_vars(None, Some("1000000".to_owned()), None);
+
+        assert!(!n.is_enabled());
+        assert!(n.notify(&NotifyState::Ready).is_ok());
+    }
+
+    #[test]
+    fn watchdog_only_pinged_when_enabled_for_this_process() {
+        let fake = FakeSocket::bind("watchdog");
+        let socket = Some(fake.path.clone().into_os_string());
+
+        let other_pid = Notifier::from_vars(
+            socket.clone(),
+            Some("1000000".to_owned()),
+            Some((std::process::id() + 1).to_string()),
+        );
+        assert_eq!(other_pid.watchdog_interval(), None);
+        other_pid.notify(&NotifyState::Watchdog).unwrap();
+        assert_eq!(fake.receive(), None);
+
+        let ours = Notifier::from_vars(
+            socket,
+            Some("1000000".to_owned()),
+            Some(std::process::id().to_string()),
+        );
+        assert_eq!(ours.watchdog_interval(), Some(Duration::from_secs(1)));
+        ours.notify(&NotifyState::Watchdog).unwrap();
+        assert_eq!(fake.receive().as_deref(), Some("WATCHDOG=1"));
+    }
+
+    #[test]
+    fn readings_fit_within_half_the_watchdog_interval() {
+        let mut config = FanshimConfig {
+            delay_between_readings: Duration::from_secs(20),
+            kick_start_time: Duration::from_secs(2),
+            ..FanshimConfig::default()
+        };
+
+        fit_watchdog(&mut config, None);
+        assert_eq!(config.delay_between_readings, Duration::from_secs(20));
+
+        fit_watchdog(&mut config, Some(Duration::from_secs(60)));
+        assert_eq!(config.delay_between_readings, Duration::from_secs(20));
+
+        fit_watchdog(&mut config, Some(Duration::from_secs(30)));
+        assert_eq!(config.delay_between_readings, Duration::from_secs(13));
+
+        // Left alone if the kick-start alone takes too long.
+        config.kick_start_time = Duration::from_secs(20);
+        fit_watchdog(&mut config, Some(Duration::from_secs(30)));
+        assert_eq!(config.delay_between_readings, Duration::from_secs(13));
+    }
+}
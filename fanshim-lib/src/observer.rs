@@ -0,0 +1,112 @@
+use crate::color::Color;
+use crate::MilliCelsius;
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+
+/// Events kept for a subscriber that has not received them yet.
+pub const CAPACITY: usize = 256;
+
+/// A threshold of the `[fan]` section, or of the schedule window overriding it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Threshold {
+    Enable,
+    Disable,
+}
+
+/// What the controller read or decided, sent to every subscriber.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// Every reading.
+    TemperatureRead {
+        raw: MilliCelsius,
+        filtered: MilliCelsius,
+    },
+    /// The filtered temperature moved past a threshold since the last reading.
+    ThresholdCrossed {
+        threshold: Threshold,
+        /// Whether the temperature is now above the threshold.
+        above: bool,
+        temperature: MilliCelsius,
+    },
+    /// The fan was turned on or off, or its speed limit changed.
+    FanStateChanged { enabled: bool, duty: f32 },
+    /// The LED was set, including to brightness 0.0 when turned off.
+    LedChanged { color: Color, brightness: f32 },
+    /// An alarm became active or cleared, as for the `on_alarm` and `on_alarm_clear` hooks.
+    Alarm { active: bool, alarms: Vec<String> },
+    /// The controller was given a new config.
+    ConfigReloaded { profile: Option<String> },
+}
+
+/// The channels events are sent to. Channels whose receiver was dropped are forgotten.
+#[derive(Debug, Default)]
+pub struct Subscribers {
+    senders: Vec<SyncSender<Event>>,
+}
+
+impl Subscribers {
+    /// A channel of at most `CAPACITY` events. While it is full, further events are dropped
+    /// for this subscriber, so one that stops receiving cannot make the controller queue
+    /// events without end.
+    pub fn subscribe(&mut self) -> Receiver<Event> {
+        let (sender, receiver) = mpsc::sync_channel(CAPACITY);
+        self.senders.push(sender);
+        receiver
+    }
+
+    pub fn emit(&mut self, event: Event) {
+        self.senders.retain(|s| match s.try_send(event.clone()) {
+            Ok(()) | Err(TrySendError::Full(_)) => true,
+            Err(TrySendError::Disconnected(_)) => false,
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.senders.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.senders.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Event, Subscribers, CAPACITY};
+
+    #[test]
+    fn sends_to_every_subscriber_until_dropped() {
+        let event = Event::ConfigReloaded { profile: None };
+        let mut subscribers = Subscribers::default();
+        let first = subscribers.subscribe();
+        let second = subscribers.subscribe();
+
+        subscribers.emit(event.clone());
+        drop(second);
+        subscribers.emit(event.clone());
+
+        assert_eq!(
+            first.try_iter().collect::<Vec<_>>(),
+            vec![event.clone(), event]
+        );
+        assert_eq!(subscribers.len(), 1);
+    }
+
+    #[test]
+    fn drops_events_for_subscribers_that_fall_behind() {
+        let mut subscribers = Subscribers::default();
+        let receiver = subscribers.subscribe();
+        let profile = |i: usize| Event::ConfigReloaded {
+            profile: Some(i.to_string()),
+        };
+
+        for i in 0..CAPACITY + 10 {
+            subscribers.emit(profile(i));
+        }
+        assert_eq!(receiver.try_iter().count(), CAPACITY);
+
+        // Kept, and receives again once it has caught up.
+        subscribers.emit(profile(0));
+        assert_eq!(receiver.try_iter().collect::<Vec<_>>(), vec![profile(0)]);
+        assert_eq!(subscribers.len(), 1);
+    }
+}
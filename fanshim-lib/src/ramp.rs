@@ -0,0 +1,137 @@
+use crate::config::FanshimConfig;
+use std::time::Duration;
+
+/// Which way the fan speed is moving.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    Up,
+    Down,
+}
+
+/// Slews the fan duty cycle towards the target set by the controller.
+///
+/// A rate of 0.0 jumps straight to the target. Speeds below the minimum fan speed are skipped,
+/// as the fan would stall there.
+pub struct Ramp {
+    up_per_second: f32,
+    down_per_second: f32,
+    min_duty: f32,
+    duty: f32,
+    target: f32,
+}
+
+impl Ramp {
+    pub fn new(config: &FanshimConfig) -> Self {
+        Self {
+            up_per_second: config.ramp_up_per_second,
+            down_per_second: config.ramp_down_per_second,
+            min_duty: config.min_fan_speed,
+            duty: 0.0,
+            target: 0.0,
+        }
+    }
+
+    /// Takes the rates of `config`, keeping the duty cycle and target.
+    pub fn reconfigure(&mut self, config: &FanshimConfig) {
+        self.up_per_second = config.ramp_up_per_second;
+        self.down_per_second = config.ramp_down_per_second;
+        self.min_duty = config.min_fan_speed;
+    }
+
+    /// Takes the fan state read from the hardware while not ramping, so changes made elsewhere,
+    /// such as by `initial_setup`, are followed.
+    pub fn sync(&mut self, fan_enabled: bool) {
+        if self.direction().is_none() && (self.duty > 0.0) != fan_enabled {
+            self.duty = if fan_enabled { 1.0 } else { 0.0 };
+            self.target = self.duty;
+        }
+    }
+
+    pub fn set_target(&mut self, duty: f32) {
+        self.target = duty.clamp(0.0, 1.0);
+    }
+
+    pub fn target(&self) -> f32 {
+        self.target
+    }
+
+    /// The duty cycle the fan was last set to.
+    pub fn duty(&self) -> f32 {
+        self.duty
+    }
+
+    /// `None` once the target is reached.
+    pub fn direction(&self) -> Option<Direction> {
+        if self.target > self.duty {
+            Some(Direction::Up)
+        } else if self.target < self.duty {
+            Some(Direction::Down)
+        } else {
+            None
+        }
+    }
+
+    /// Moves the duty cycle as far towards the target as the rates allow in `elapsed`.
+    /// Returns the new duty cycle if it changed.
+    pub fn step(&mut self, elapsed: Duration) -> Option<f32> {
+        let seconds = elapsed.as_secs_f32();
+        let duty = match self.direction()? {
+            Direction::Up => {
+                let mut duty = if self.up_per_second > 0.0 {
+                    self.duty + self.up_per_second * seconds
+                } else {
+                    self.target
+                };
+                if self.duty == 0.0 {
+                    duty = duty.max(self.min_duty);
+                }
+                duty.min(self.target)
+            }
+            Direction::Down => {
+                let duty = if self.down_per_second > 0.0 {
+                    (self.duty - self.down_per_second * seconds).max(self.target)
+                } else {
+                    self.target
+                };
+                if duty < self.min_duty {
+                    self.target
+                } else {
+                    duty
+                }
+            }
+        };
+        self.duty = duty;
+        Some(duty)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Direction, Ramp};
+    use crate::config::FanshimConfig;
+    use std::time::Duration;
+
+    #[test]
+    fn ramps_at_configured_rates_and_skips_stall_speeds() {
+        let mut ramp = Ramp::new(&FanshimConfig {
+            ramp_up_per_second: 0.1,
+            ramp_down_per_second: 0.0,
+            min_fan_speed: 0.3,
+            ..FanshimConfig::default()
+        });
+        let second = Duration::from_secs(1);
+
+        ramp.set_target(0.5);
+        assert_eq!(ramp.direction(), Some(Direction::Up));
+        // Straight to the minimum speed, then 10% per second.
+        assert_eq!(ramp.step(second), Some(0.3));
+        assert!((ramp.step(second).unwrap() - 0.4).abs() < 1e-6);
+        assert_eq!(ramp.step(Duration::from_secs(5)), Some(0.5));
+        assert_eq!(ramp.step(second), None);
+
+        // No rate down, so it stops at once.
+        ramp.set_target(0.0);
+        assert_eq!(ramp.step(second), Some(0.0));
+        assert_eq!(ramp.direction(), None);
+    }
+}
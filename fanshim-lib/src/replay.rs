@@ -0,0 +1,305 @@
+use crate::cancel::CancellationToken;
+use crate::clock::VirtualClock;
+use crate::cpu::Hertz;
+use crate::hooks::Trigger;
+use crate::notify::NotifyState;
+use crate::sim::{Command, LoggedCommand};
+use crate::status::Indication;
+use crate::throttle::Throttled;
+use crate::{MilliCelsius, ShimLayer, MILLI_CELSIUS_IN_CELSIUS};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A recorded temperature reading.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sample {
+    /// Seconds since the unix epoch, or any other fixed point.
+    pub timestamp: u64,
+    pub temperature: MilliCelsius,
+}
+
+/// Reads a trace of `timestamp,temperature` lines, such as a history file.
+///
+/// Temperatures are in millidegrees celsius, or in degrees celsius if the header is
+/// `timestamp,celsius`. Further columns and lines that can not be parsed are ignored.
+pub fn read_trace(path: &Path) -> io::Result<Vec<Sample>> {
+    parse_trace(BufReader::new(File::open(path)?))
+}
+
+pub fn parse_trace<R: BufRead>(r: R) -> io::Result<Vec<Sample>> {
+    let mut scale = 1.0;
+    let mut samples = Vec::new();
+    for (i, line) in r.lines().enumerate() {
+        let line = line?;
+        let mut fields = line.trim().split(',').map(str::trim);
+        let (timestamp, temperature) = match (fields.next(), fields.next()) {
+            (Some(t), Some(c)) => (t, c),
+            _ => continue,
+        };
+        if i == 0 && temperature == "celsius" {
+            scale = f64::from(MILLI_CELSIUS_IN_CELSIUS);
+        }
+        if let (Ok(timestamp), Ok(temperature)) = (timestamp.parse(), temperature.parse::<f64>()) {
+            samples.push(Sample {
+                timestamp,
+                temperature: MilliCelsius((temperature * scale).round() as i32),
+            });
+        }
+    }
+
+    if samples.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the trace contains no readings",
+        ));
+    }
+    samples.sort_by_key(|s| s.timestamp);
+    Ok(samples)
+}
+
+/// Outcome of replaying a trace.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Summary {
+    /// Time between the first and the last reading of the trace.
+    pub duration: Duration,
+    /// Number of times the fan was turned on or off, not counting `initial_setup`.
+    pub toggles: usize,
+    pub fan_on_time: Duration,
+    pub max_temperature: MilliCelsius,
+}
+
+/// A `ShimLayer` that plays back a recorded trace on a virtual clock.
+///
+/// `sleep` returns immediately and only moves the clock, so a day of readings replays in
+/// milliseconds. The `cancellation_token` is cancelled once the clock passes the end of the trace.
+pub struct ReplayShim {
+    trace: Vec<Sample>,
+    position: usize,
+    clock: VirtualClock,
+    token: CancellationToken,
+    fan_on: Option<bool>,
+    toggles: usize,
+    fan_on_time: Duration,
+    max_temperature: MilliCelsius,
+    commands: Vec<LoggedCommand>,
+}
+
+impl ReplayShim {
+    /// Panics if `trace` is empty, which `read_trace` never returns.
+    pub fn new(trace: Vec<Sample>) -> Self {
+        assert!(!trace.is_empty(), "a trace needs at least one reading");
+        let max_temperature = trace[0].temperature;
+        let start = UNIX_EPOCH + Duration::from_secs(trace[0].timestamp);
+        Self {
+            trace,
+            clock: VirtualClock::new(start),
+            token: CancellationToken::new(),
+            position: 0,
+            fan_on: None,
+            toggles: 0,
+            fan_on_time: Duration::from_secs(0),
+            max_temperature,
+            commands: Vec::new(),
+        }
+    }
+
+    /// Token to pass to `main_loop`, so it returns at the end of the trace.
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.token.clone()
+    }
+
+    /// Every fan and LED command, with the time since the start of the trace.
+    pub fn commands(&self) -> &[LoggedCommand] {
+        &self.commands
+    }
+
+    pub fn summary(&self) -> Summary {
+        Summary {
+            duration: self.duration(),
+            toggles: self.toggles,
+            fan_on_time: self.fan_on_time,
+            max_temperature: self.max_temperature,
+        }
+    }
+
+    fn duration(&self) -> Duration {
+        Duration::from_secs(self.trace[self.trace.len() - 1].timestamp - self.trace[0].timestamp)
+    }
+
+    fn set_fan(&mut self, on: bool, command: Command) {
+        if self.fan_on.is_some_and(|was_on| was_on != on) {
+            self.toggles += 1;
+        }
+        self.fan_on = Some(on);
+        self.log(command);
+    }
+
+    fn log(&mut self, command: Command) {
+        self.commands.push(LoggedCommand {
+            elapsed: self.clock.elapsed(),
+            command,
+        });
+    }
+}
+
+impl ShimLayer for ReplayShim {
+    /// The latest reading at or before the virtual clock.
+    fn get_cpu_temperature(&mut self) -> MilliCelsius {
+        let now = self.trace[0].timestamp + self.clock.elapsed().as_secs();
+        while self
+            .trace
+            .get(self.position + 1)
+            .is_some_and(|next| next.timestamp <= now)
+        {
+            self.position += 1;
+        }
+
+        let temperature = self.trace[self.position].temperature;
+        if temperature.0 > self.max_temperature.0 {
+            self.max_temperature = temperature;
+        }
+        temperature
+    }
+
+    fn get_cpu_load(&mut self) -> Option<f32> {
+        None
+    }
+
+    fn get_cpu_frequency(&mut self) -> Option<Hertz> {
+        None
+    }
+
+    fn get_throttled(&mut self) -> Option<Throttled> {
+        None
+    }
+
+    fn get_status(&mut self) -> Option<Indication> {
+        None
+    }
+
+    fn fan_is_enabled(&mut self) -> bool {
+        self.fan_on.unwrap_or(false)
+    }
+
+    fn turn_fan_on(&mut self) {
+        self.set_fan(true, Command::FanOn);
+    }
+
+    fn turn_fan_off(&mut self) {
+        self.set_fan(false, Command::FanOff);
+    }
+
+    fn set_fan_duty(&mut self, duty: f32) {
+        self.set_fan(duty > 0.0, Command::FanDuty(duty));
+    }
+
+    fn set_led_rgb_brightness(&mut self, r: u8, g: u8, b: u8, brightness: f32) {
+        self.log(Command::Led {
+            r,
+            g,
+            b,
+            brightness,
+        });
+    }
+
+    fn button_pressed(&mut self) -> bool {
+        false
+    }
+
+    /// Only logs the event, as simulated state changes should not act on the system.
+    fn run_hook(&mut self, trigger: Trigger) {
+        self.log(Command::Hook(trigger.event));
+    }
+
+    fn sleep(&mut self, dur: Duration) {
+        // Only time within the trace counts towards the summary.
+        let remaining = self.duration().saturating_sub(self.clock.elapsed());
+        if self.fan_is_enabled() {
+            self.fan_on_time += dur.min(remaining);
+        }
+        self.clock.advance(dur);
+        if self.clock.elapsed() > self.duration() {
+            self.token.cancel();
+        }
+    }
+
+    fn now(&self) -> SystemTime {
+        self.clock.now()
+    }
+
+    fn notify(&mut self, _state: NotifyState) {}
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_trace, ReplayShim, Sample};
+    use crate::config::FanshimConfig;
+    use crate::{initial_setup, main_loop, MilliCelsius};
+    use std::time::Duration;
+
+    #[test]
+    fn parses_history_files_and_celsius_traces() {
+        let history = "timestamp,temperature,duty,event\n\
+                       20,51000,0,reading\n\
+                       10,50000,0,reading\n\
+                       garbage\n";
+        assert_eq!(
+            parse_trace(history.as_bytes()).unwrap(),
+            vec![
+                Sample {
+                    timestamp: 10,
+                    temperature: MilliCelsius(50_000)
+                },
+                Sample {
+                    timestamp: 20,
+                    temperature: MilliCelsius(51_000)
+                },
+            ]
+        );
+
+        let celsius = "timestamp,celsius\n0,48.5\n";
+        assert_eq!(
+            parse_trace(celsius.as_bytes()).unwrap()[0].temperature,
+            MilliCelsius(48_500)
+        );
+
+        assert!(parse_trace("timestamp,temperature\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn replays_trace_through_main_loop() {
+        let config = FanshimConfig {
+            delay_between_readings: Duration::from_secs(10),
+            ..FanshimConfig::default()
+        };
+        // Cold for a minute, hot for five, cold for the rest of the quarter hour.
+        let trace = (0..=900)
+            .step_by(10)
+            .map(|t| Sample {
+                timestamp: 1_000 + t,
+                temperature: MilliCelsius(if (60..360).contains(&t) {
+                    config.enable_fan_at_temperature.0 + 5_000
+                } else {
+                    config.disable_fan_at_temperature.0 - 5_000
+                }),
+            })
+            .collect();
+
+        let mut s = ReplayShim::new(trace);
+        initial_setup(&config, &mut s);
+        let token = s.cancellation_token();
+        main_loop(&config, &mut s, &token);
+
+        let summary = s.summary();
+        assert_eq!(summary.duration, Duration::from_secs(900));
+        assert_eq!(summary.toggles, 2);
+        // Each change waits for `number_of_readings_before_action` readings.
+        assert_eq!(summary.fan_on_time, Duration::from_secs(300));
+        assert_eq!(
+            summary.max_temperature,
+            MilliCelsius(config.enable_fan_at_temperature.0 + 5_000)
+        );
+    }
+}
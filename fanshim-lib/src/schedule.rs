@@ -0,0 +1,265 @@
+use crate::config::FanshimConfig;
+use crate::{MilliCelsius, MILLI_CELSIUS_IN_CELSIUS};
+use chrono::{DateTime, Datelike, Local, NaiveDateTime, Timelike};
+use std::convert::TryFrom;
+use std::fmt;
+use std::time::SystemTime;
+
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Weekday {
+    Mon,
+    Tue,
+    Wed,
+    Thu,
+    Fri,
+    Sat,
+    Sun,
+}
+
+impl From<chrono::Weekday> for Weekday {
+    fn from(w: chrono::Weekday) -> Self {
+        match w {
+            chrono::Weekday::Mon => Weekday::Mon,
+            chrono::Weekday::Tue => Weekday::Tue,
+            chrono::Weekday::Wed => Weekday::Wed,
+            chrono::Weekday::Thu => Weekday::Thu,
+            chrono::Weekday::Fri => Weekday::Fri,
+            chrono::Weekday::Sat => Weekday::Sat,
+            chrono::Weekday::Sun => Weekday::Sun,
+        }
+    }
+}
+
+/// Local time of day, written as "HH:MM" in the config file.
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(try_from = "String")]
+pub struct TimeOfDay {
+    minutes: u32,
+}
+
+impl TryFrom<String> for TimeOfDay {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        let invalid = || format!("invalid time of day '{}', expected e.g. \"23:00\"", s);
+        let (hours, minutes) = s.split_once(':').ok_or_else(invalid)?;
+        let hours: u32 = hours.parse().map_err(|_| invalid())?;
+        let minutes: u32 = minutes.parse().map_err(|_| invalid())?;
+        if hours >= 24 || minutes >= 60 {
+            return Err(invalid());
+        }
+        Ok(Self {
+            minutes: hours * 60 + minutes,
+        })
+    }
+}
+
+impl TimeOfDay {
+    pub fn minutes_since_midnight(self) -> u32 {
+        self.minutes
+    }
+}
+
+impl serde::Serialize for TimeOfDay {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl fmt::Display for TimeOfDay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}:{:02}", self.minutes / 60, self.minutes % 60)
+    }
+}
+
+fn every_day() -> Vec<Weekday> {
+    vec![
+        Weekday::Mon,
+        Weekday::Tue,
+        Weekday::Wed,
+        Weekday::Thu,
+        Weekday::Fri,
+        Weekday::Sat,
+        Weekday::Sun,
+    ]
+}
+
+/// A `[[schedule]]` section: a daily time window that overrides some settings.
+///
+/// Windows may cross midnight, in which case `weekdays` are the days the window starts on.
+/// A window that starts and ends at the same time lasts 24 hours.
+#[derive(serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct Window {
+    pub start: TimeOfDay,
+    pub end: TimeOfDay,
+    #[serde(default = "every_day")]
+    pub weekdays: Vec<Weekday>,
+    pub enable_fan_at_degrees_celsius: Option<i32>,
+    pub disable_fan_at_degrees_celsius: Option<i32>,
+    /// Value from 0.0 to 1.0.
+    pub max_fan_speed: Option<f32>,
+    pub led_brightness: Option<f32>,
+}
+
+impl Window {
+    pub fn contains(&self, time: NaiveDateTime) -> bool {
+        let minute = time.hour() * 60 + time.minute();
+        let today = Weekday::from(time.weekday());
+        let yesterday = Weekday::from(time.weekday().pred());
+        let (start, end) = (self.start.minutes, self.end.minutes);
+
+        if start < end {
+            self.weekdays.contains(&today) && start <= minute && minute < end
+        } else {
+            // Crosses midnight, or lasts 24 hours if start == end.
+            (self.weekdays.contains(&today) && minute >= start)
+                || (self.weekdays.contains(&yesterday) && minute < end)
+        }
+    }
+}
+
+impl fmt::Display for Window {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.start, self.end)
+    }
+}
+
+/// The thresholds and limits `main_loop` acts on at a given time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Settings {
+    pub enable_fan_at_temperature: MilliCelsius,
+    pub disable_fan_at_temperature: MilliCelsius,
+    pub max_fan_speed: f32,
+    pub led_brightness: f32,
+}
+
+impl Settings {
+    /// The config's own settings, overridden by those of `window`.
+    pub fn new(config: &FanshimConfig, window: Option<&Window>) -> Self {
+        let celsius = |c: i32| MilliCelsius(c * MILLI_CELSIUS_IN_CELSIUS);
+        let w = window;
+        Self {
+            enable_fan_at_temperature: w
+                .and_then(|w| w.enable_fan_at_degrees_celsius)
+                .map_or(config.enable_fan_at_temperature, celsius),
+            disable_fan_at_temperature: w
+                .and_then(|w| w.disable_fan_at_degrees_celsius)
+                .map_or(config.disable_fan_at_temperature, celsius),
+            max_fan_speed: w
+                .and_then(|w| w.max_fan_speed)
+                .unwrap_or(config.max_fan_speed)
+                .clamp(0.0, 1.0),
+            led_brightness: w
+                .and_then(|w| w.led_brightness)
+                .unwrap_or(config.led_brightness),
+        }
+    }
+}
+
+/// Index of the first window in `schedule` that contains `time`.
+pub fn active_window(schedule: &[Window], time: NaiveDateTime) -> Option<usize> {
+    schedule.iter().position(|w| w.contains(time))
+}
+
+pub fn local_time(time: SystemTime) -> NaiveDateTime {
+    DateTime::<Local>::from(time).naive_local()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{active_window, Settings, Window};
+    use crate::config::{FanshimConfig, FanshimInterimConfig};
+    use crate::MilliCelsius;
+    use chrono::NaiveDate;
+
+    fn schedule(toml: &str) -> Vec<Window> {
+        toml::from_str::<FanshimInterimConfig>(toml)
+            .unwrap()
+            .schedule
+    }
+
+    #[test]
+    fn window_crosses_midnight_on_selected_weekdays() {
+        let s = schedule(
+            r#"
+            [[schedule]]
+            start = "23:00"
+            end = "07:00"
+            weekdays = ["mon", "tue", "wed", "thu", "fri"]
+            "#,
+        );
+        // 2020-10-02 is a Friday.
+        let at = |day, h, m| NaiveDate::from_ymd(2020, 10, day).and_hms(h, m, 0);
+
+        assert_eq!(active_window(&s, at(2, 22, 59)), None);
+        assert_eq!(active_window(&s, at(2, 23, 0)), Some(0));
+        assert_eq!(active_window(&s, at(3, 6, 59)), Some(0));
+        assert_eq!(active_window(&s, at(3, 7, 0)), None);
+        // Saturday night is not selected, so neither is Sunday morning.
+        assert_eq!(active_window(&s, at(3, 23, 30)), None);
+        assert_eq!(active_window(&s, at(4, 3, 0)), None);
+        // Early Monday still belongs to Sunday night, which is not selected.
+        assert_eq!(active_window(&s, at(5, 3, 0)), None);
+        assert_eq!(active_window(&s, at(5, 23, 30)), Some(0));
+    }
+
+    #[test]
+    fn first_matching_window_wins() {
+        let s = schedule(
+            r#"
+            [[schedule]]
+            start = "12:00"
+            end = "13:00"
+
+            [[schedule]]
+            start = "08:00"
+            end = "08:00"
+            "#,
+        );
+        let at = |h| NaiveDate::from_ymd(2020, 10, 2).and_hms(h, 30, 0);
+
+        assert_eq!(active_window(&s, at(12)), Some(0));
+        assert_eq!(active_window(&s, at(3)), Some(1));
+        assert_eq!(active_window(&s, at(20)), Some(1));
+    }
+
+    #[test]
+    fn window_overrides_settings() {
+        let s = schedule(
+            r#"
+            [[schedule]]
+            start = "23:00"
+            end = "07:00"
+            enable_fan_at_degrees_celsius = 70
+            led_brightness = 0.0
+            "#,
+        );
+        let c = FanshimConfig::default();
+
+        let night = Settings::new(&c, s.first());
+        assert_eq!(night.enable_fan_at_temperature, MilliCelsius(70_000));
+        assert_eq!(
+            night.disable_fan_at_temperature,
+            c.disable_fan_at_temperature
+        );
+        assert_eq!(night.max_fan_speed, 1.0);
+        assert_eq!(night.led_brightness, 0.0);
+
+        let day = Settings::new(&c, None);
+        assert_eq!(day.enable_fan_at_temperature, c.enable_fan_at_temperature);
+        assert_eq!(day.led_brightness, c.led_brightness);
+    }
+
+    #[test]
+    fn rejects_invalid_times() {
+        assert!(toml::from_str::<FanshimInterimConfig>(
+            "[[schedule]]\nstart = \"24:00\"\nend = \"07:00\"\n"
+        )
+        .is_err());
+        assert!(toml::from_str::<FanshimInterimConfig>(
+            "[[schedule]]\nstart = \"7pm\"\nend = \"07:00\"\n"
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,267 @@
+use crate::clock::VirtualClock;
+use crate::config::Simulation;
+use crate::cpu::Hertz;
+use crate::hooks::{Event, Trigger};
+use crate::notify::NotifyState;
+use crate::status::Indication;
+use crate::throttle::Throttled;
+use crate::{MilliCelsius, ShimLayer, MILLI_CELSIUS_IN_CELSIUS};
+use log::{debug, info};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// Longest step the thermal model is integrated over at once.
+const MAX_MODEL_STEP: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    FanOn,
+    FanOff,
+    FanDuty(f32),
+    Led {
+        r: u8,
+        g: u8,
+        b: u8,
+        brightness: f32,
+    },
+    Hook(Event),
+}
+
+/// A fan or LED command, with the simulated time it was sent at.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LoggedCommand {
+    pub elapsed: Duration,
+    pub command: Command,
+}
+
+/// A `ShimLayer` that simulates a Pi with a Fan SHIM, for running the driver without the hardware.
+///
+/// The CPU heats up proportionally to the configured load and cools towards the ambient
+/// temperature, faster the faster the fan runs.
+pub struct SimShim {
+    config: Simulation,
+    temperature: f64,
+    fan_duty: f32,
+    clock: VirtualClock,
+    /// `None` unless `record_commands` was called, as the simulated driver runs indefinitely.
+    commands: Option<Vec<LoggedCommand>>,
+}
+
+impl SimShim {
+    pub fn new(config: Simulation) -> Self {
+        Self {
+            temperature: config.ambient_degrees_celsius,
+            config,
+            fan_duty: 0.0,
+            clock: VirtualClock::new(SystemTime::now()),
+            commands: None,
+        }
+    }
+
+    /// Changes the synthetic CPU load, from 0.0 (idle) to 1.0 (fully loaded).
+    pub fn set_cpu_load(&mut self, load: f64) {
+        self.config.cpu_load = load.clamp(0.0, 1.0);
+    }
+
+    pub fn temperature(&self) -> f64 {
+        self.temperature
+    }
+
+    /// Simulated time since the shim was created.
+    pub fn elapsed(&self) -> Duration {
+        self.clock.elapsed()
+    }
+
+    /// Keeps the fan and LED commands received from now on, for `commands`.
+    pub fn record_commands(&mut self) {
+        self.commands.get_or_insert_with(Vec::new);
+    }
+
+    /// Every fan and LED command received since `record_commands`.
+    pub fn commands(&self) -> &[LoggedCommand] {
+        self.commands.as_deref().unwrap_or_default()
+    }
+
+    /// Advances the thermal model by `dur` without sleeping.
+    pub fn advance(&mut self, dur: Duration) {
+        let mut remaining = dur;
+        while remaining > Duration::from_secs(0) {
+            let step = remaining.min(MAX_MODEL_STEP);
+            remaining -= step;
+
+            let cooling = self.config.passive_cooling_per_second
+                * (1.0 + self.config.fan_cooling_factor * f64::from(self.fan_duty));
+            let change = self.config.heating_degrees_celsius_per_second * self.config.cpu_load
+                - cooling * (self.temperature - self.config.ambient_degrees_celsius);
+            self.temperature += change * step.as_secs_f64();
+        }
+        self.clock.advance(dur);
+    }
+
+    fn log(&mut self, command: Command) {
+        info!(
+            "Simulation at {:.1}s and {:.1}°C: {:?}",
+            self.elapsed().as_secs_f64(),
+            self.temperature,
+            command
+        );
+        let elapsed = self.elapsed();
+        if let Some(commands) = &mut self.commands {
+            commands.push(LoggedCommand { elapsed, command });
+        }
+    }
+}
+
+impl ShimLayer for SimShim {
+    fn get_cpu_temperature(&mut self) -> MilliCelsius {
+        MilliCelsius((self.temperature * f64::from(MILLI_CELSIUS_IN_CELSIUS)).round() as i32)
+    }
+
+    fn get_cpu_load(&mut self) -> Option<f32> {
+        Some(self.config.cpu_load as f32)
+    }
+
+    fn get_cpu_frequency(&mut self) -> Option<Hertz> {
+        None
+    }
+
+    fn get_throttled(&mut self) -> Option<Throttled> {
+        None
+    }
+
+    fn get_status(&mut self) -> Option<Indication> {
+        None
+    }
+
+    fn fan_is_enabled(&mut self) -> bool {
+        self.fan_duty > 0.0
+    }
+
+    fn turn_fan_on(&mut self) {
+        self.fan_duty = 1.0;
+        self.log(Command::FanOn);
+    }
+
+    fn turn_fan_off(&mut self) {
+        self.fan_duty = 0.0;
+        self.log(Command::FanOff);
+    }
+
+    fn set_fan_duty(&mut self, duty: f32) {
+        self.fan_duty = duty.clamp(0.0, 1.0);
+        self.log(Command::FanDuty(self.fan_duty));
+    }
+
+    fn set_led_rgb_brightness(&mut self, r: u8, g: u8, b: u8, brightness: f32) {
+        self.log(Command::Led {
+            r,
+            g,
+            b,
+            brightness,
+        });
+    }
+
+    fn button_pressed(&mut self) -> bool {
+        false
+    }
+
+    /// Only logs the event, as simulated state changes should not act on the system.
+    fn run_hook(&mut self, trigger: Trigger) {
+        self.log(Command::Hook(trigger.event));
+    }
+
+    fn sleep(&mut self, dur: Duration) {
+        self.advance(dur);
+        if self.config.time_scale > 0.0 {
+            thread::sleep(dur.div_f64(self.config.time_scale));
+        }
+    }
+
+    fn now(&self) -> SystemTime {
+        self.clock.now()
+    }
+
+    fn notify(&mut self, state: NotifyState) {
+        debug!("Simulation: {:?}", state);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Command, SimShim};
+    use crate::config::Simulation;
+    use crate::ShimLayer;
+    use std::time::Duration;
+
+    fn simulation() -> SimShim {
+        SimShim::new(Simulation {
+            time_scale: 0.0,
+            ..Simulation::default()
+        })
+    }
+
+    #[test]
+    fn heats_up_under_load() {
+        let mut s = simulation();
+        let idle = s.get_cpu_temperature();
+
+        s.set_cpu_load(1.0);
+        s.sleep(Duration::from_secs(60));
+
+        assert!(s.get_cpu_temperature().0 > idle.0 + 10_000);
+        assert_eq!(s.elapsed(), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn cools_faster_with_fan_on() {
+        let mut without_fan = simulation();
+        let mut with_fan = simulation();
+        for s in [&mut without_fan, &mut with_fan].iter_mut() {
+            s.set_cpu_load(1.0);
+            s.sleep(Duration::from_secs(600));
+            s.set_cpu_load(0.2);
+        }
+
+        with_fan.turn_fan_on();
+        without_fan.sleep(Duration::from_secs(60));
+        with_fan.sleep(Duration::from_secs(60));
+
+        assert!(with_fan.temperature() < without_fan.temperature() - 5.0);
+    }
+
+    #[test]
+    fn logs_commands() {
+        let mut s = simulation();
+        s.turn_fan_off();
+        assert!(s.commands().is_empty());
+        s.record_commands();
+
+        s.turn_fan_on();
+        s.sleep(Duration::from_secs(5));
+        s.set_led_rgb_brightness(255, 0, 0, 0.1);
+        s.turn_fan_off();
+
+        let commands: Vec<(u64, Command)> = s
+            .commands()
+            .iter()
+            .map(|c| (c.elapsed.as_secs(), c.command))
+            .collect();
+        assert_eq!(
+            commands,
+            vec![
+                (0, Command::FanOn),
+                (
+                    5,
+                    Command::Led {
+                        r: 255,
+                        g: 0,
+                        b: 0,
+                        brightness: 0.1
+                    }
+                ),
+                (5, Command::FanOff),
+            ]
+        );
+        assert!(!s.fan_is_enabled());
+    }
+}
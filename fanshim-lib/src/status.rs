@@ -0,0 +1,414 @@
+use crate::color::Color;
+use crate::throttle::{self, Condition};
+use log::{info, warn};
+use std::ffi::CString;
+use std::fmt;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use std::{fs, io, mem};
+
+const PROC_NET_ROUTE: &str = "/proc/net/route";
+/// Longest the checking thread sleeps, so it notices being stopped.
+const MAX_SLEEP: Duration = Duration::from_secs(1);
+
+/// How the LED shows a status.
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Pattern {
+    #[default]
+    Solid,
+    /// On and off on alternate readings.
+    Flash,
+}
+
+/// What the LED shows while a status is active.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Indication {
+    /// What the status is about, e.g. "network down".
+    pub name: String,
+    pub color: Color,
+    pub pattern: Pattern,
+}
+
+/// A condition of the system a `[[status]]` section watches for.
+#[derive(serde::Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "condition", rename_all = "snake_case")]
+pub enum Check {
+    /// There is no default route.
+    NetworkDown,
+    /// The file system holding `path` is fuller than `above_percent`.
+    DiskUsage {
+        #[serde(default = "root")]
+        path: PathBuf,
+        above_percent: f32,
+    },
+    /// `unit` failed, or any unit if none is given.
+    UnitFailed { unit: Option<String> },
+    /// The firmware reports under-voltage.
+    UnderVoltage,
+}
+
+fn root() -> PathBuf {
+    PathBuf::from("/")
+}
+
+impl Check {
+    pub fn provider(&self) -> Box<dyn Provider> {
+        match self.clone() {
+            Check::NetworkDown => Box::new(NetworkDown),
+            Check::DiskUsage {
+                path,
+                above_percent,
+            } => Box::new(DiskUsage {
+                path,
+                above_percent,
+            }),
+            Check::UnitFailed { unit } => Box::new(UnitFailed { unit }),
+            Check::UnderVoltage => Box::new(UnderVoltage),
+        }
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Check::NetworkDown => f.write_str("network down"),
+            Check::DiskUsage {
+                path,
+                above_percent,
+            } => {
+                write!(
+                    f,
+                    "disk usage of {} above {}%",
+                    path.display(),
+                    above_percent
+                )
+            }
+            Check::UnitFailed { unit: Some(unit) } => write!(f, "unit {} failed", unit),
+            Check::UnitFailed { unit: None } => f.write_str("unit failed"),
+            Check::UnderVoltage => f.write_str("under-voltage"),
+        }
+    }
+}
+
+fn default_seconds_between_checks() -> u64 {
+    30
+}
+
+/// A `[[status]]` section: shows `color` on the LED while the condition holds.
+#[derive(serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct Rule {
+    #[serde(flatten)]
+    pub check: Check,
+    pub color: Color,
+    #[serde(default)]
+    pub pattern: Pattern,
+    /// The active rule with the highest priority is shown, the first one of equal priorities.
+    #[serde(default)]
+    pub priority: i32,
+    #[serde(default = "default_seconds_between_checks")]
+    pub seconds_between_checks: u64,
+}
+
+/// Tells whether a condition holds. Implement it to add conditions beyond the built-in checks.
+pub trait Provider: Send {
+    fn is_active(&mut self) -> io::Result<bool>;
+}
+
+struct NetworkDown;
+
+impl Provider for NetworkDown {
+    fn is_active(&mut self) -> io::Result<bool> {
+        Ok(!has_default_route(&fs::read_to_string(PROC_NET_ROUTE)?))
+    }
+}
+
+/// Whether the contents of /proc/net/route have a default route that is up.
+fn has_default_route(routes: &str) -> bool {
+    const RTF_UP: u32 = 0x1;
+    routes.lines().skip(1).any(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match (fields.get(1), fields.get(3)) {
+            (Some(&destination), Some(flags)) => {
+                destination == "00000000"
+                    && u32::from_str_radix(flags, 16).is_ok_and(|f| f & RTF_UP != 0)
+            }
+            _ => false,
+        }
+    })
+}
+
+struct DiskUsage {
+    path: PathBuf,
+    above_percent: f32,
+}
+
+impl Provider for DiskUsage {
+    fn is_active(&mut self) -> io::Result<bool> {
+        Ok(disk_usage_percent(&self.path)? > self.above_percent)
+    }
+}
+
+// The block counts are u32 on some targets.
+#[allow(clippy::unnecessary_cast)]
+fn disk_usage_percent(path: &Path) -> io::Result<f32> {
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    // Safe because statvfs only writes to the struct it is given.
+    let mut stat: libc::statvfs = unsafe { mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(usage_percent(
+        stat.f_blocks as u64,
+        stat.f_bfree as u64,
+        stat.f_bavail as u64,
+    ))
+}
+
+/// Share of the blocks available to users that are used, as `df` shows it.
+fn usage_percent(blocks: u64, free: u64, available: u64) -> f32 {
+    let used = blocks.saturating_sub(free);
+    let usable = used + available;
+    if usable == 0 {
+        0.0
+    } else {
+        used as f32 / usable as f32 * 100.0
+    }
+}
+
+struct UnitFailed {
+    unit: Option<String>,
+}
+
+impl Provider for UnitFailed {
+    fn is_active(&mut self) -> io::Result<bool> {
+        match &self.unit {
+            Some(unit) => Ok(Command::new("systemctl")
+                .args(["is-failed", "--quiet", unit])
+                .status()?
+                .success()),
+            None => {
+                // Exits with an error unless running, so only the output counts.
+                let output = Command::new("systemctl")
+                    .arg("is-system-running")
+                    .output()?;
+                Ok(String::from_utf8_lossy(&output.stdout).trim() == "degraded")
+            }
+        }
+    }
+}
+
+struct UnderVoltage;
+
+impl Provider for UnderVoltage {
+    fn is_active(&mut self) -> io::Result<bool> {
+        Ok(throttle::read()?.is_active(Condition::UnderVoltage))
+    }
+}
+
+/// A provider checked every `interval`, shown with `indication` while active.
+pub struct Source {
+    pub name: String,
+    pub provider: Box<dyn Provider>,
+    pub interval: Duration,
+    pub indication: Indication,
+    pub priority: i32,
+}
+
+impl From<&Rule> for Source {
+    fn from(rule: &Rule) -> Self {
+        Self {
+            name: rule.check.to_string(),
+            provider: rule.check.provider(),
+            interval: Duration::from_secs(rule.seconds_between_checks),
+            indication: Indication {
+                name: rule.check.to_string(),
+                color: rule.color,
+                pattern: rule.pattern,
+            },
+            priority: rule.priority,
+        }
+    }
+}
+
+/// Priority and indication of a source, while it is active.
+type Active = Option<(i32, Indication)>;
+
+/// Checks sources on a thread of its own, each at its own interval, until dropped.
+pub struct Monitor {
+    active: Arc<Mutex<Vec<Active>>>,
+    stop: Arc<AtomicBool>,
+}
+
+impl Monitor {
+    pub fn start(sources: Vec<Source>) -> Self {
+        let active = Arc::new(Mutex::new(vec![None; sources.len()]));
+        let stop = Arc::new(AtomicBool::new(false));
+        let monitor = Self {
+            active: Arc::clone(&active),
+            stop: Arc::clone(&stop),
+        };
+
+        thread::spawn(move || {
+            let mut sources = sources;
+            let mut due = vec![Instant::now(); sources.len()];
+            let mut failing = vec![false; sources.len()];
+            while !stop.load(Ordering::SeqCst) {
+                let now = Instant::now();
+                for (i, source) in sources.iter_mut().enumerate() {
+                    if due[i] > now {
+                        continue;
+                    }
+                    due[i] = now + source.interval.max(MAX_SLEEP);
+
+                    let is_active = match source.provider.is_active() {
+                        Ok(a) => {
+                            failing[i] = false;
+                            a
+                        }
+                        Err(e) => {
+                            if !failing[i] {
+                                warn!("Unable to check status {}: {}", source.name, e);
+                            }
+                            failing[i] = true;
+                            false
+                        }
+                    };
+                    let mut active = active.lock().unwrap();
+                    if is_active != active[i].is_some() {
+                        if is_active {
+                            info!("Status {} is active", source.name);
+                        } else {
+                            info!("Status {} is no longer active", source.name);
+                        }
+                    }
+                    active[i] =
+                        Some((source.priority, source.indication.clone())).filter(|_| is_active);
+                }
+
+                let next = due.iter().min().map_or(MAX_SLEEP, |d| {
+                    d.saturating_duration_since(Instant::now()).min(MAX_SLEEP)
+                });
+                thread::sleep(next);
+            }
+        });
+
+        monitor
+    }
+
+    /// What the LED should show for the active sources, if any is.
+    pub fn current(&self) -> Option<Indication> {
+        highest(&self.active.lock().unwrap())
+    }
+}
+
+impl Drop for Monitor {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
+
+/// The indication of the active source with the highest priority, the first of equal ones.
+fn highest(active: &[Active]) -> Option<Indication> {
+    active
+        .iter()
+        .flatten()
+        .rev()
+        .max_by_key(|(priority, _)| *priority)
+        .map(|(_, indication)| indication.clone())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{has_default_route, highest, usage_percent, Check, Indication, Pattern, Rule};
+    use crate::color::Color;
+    use crate::config::FanshimConfig;
+    use std::path::PathBuf;
+
+    #[test]
+    fn parses_rules() {
+        let rules: Vec<Rule> = toml::from_str::<crate::config::FanshimInterimConfig>(
+            r#"
+            [[status]]
+            condition = "network_down"
+            color = "blue"
+            pattern = "flash"
+            priority = 10
+
+            [[status]]
+            condition = "disk_usage"
+            above_percent = 90.0
+            color = "orange"
+            seconds_between_checks = 300
+
+            [[status]]
+            condition = "unit_failed"
+            unit = "nginx.service"
+            color = "yellow"
+            "#,
+        )
+        .map(|c| FanshimConfig::from(c).status)
+        .unwrap();
+
+        assert_eq!(rules[0].check, Check::NetworkDown);
+        assert_eq!(rules[0].pattern, Pattern::Flash);
+        assert_eq!(rules[0].seconds_between_checks, 30);
+        assert_eq!(
+            rules[1].check,
+            Check::DiskUsage {
+                path: PathBuf::from("/"),
+                above_percent: 90.0
+            }
+        );
+        assert_eq!(rules[1].priority, 0);
+        assert_eq!(rules[2].check.to_string(), "unit nginx.service failed");
+    }
+
+    #[test]
+    fn finds_default_route() {
+        let header = "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\n";
+        let local = "wlan0\t0001A8C0\t00000000\t0001\t0\t0\t303\t00FFFFFF\n";
+        let default = "wlan0\t00000000\t0101A8C0\t0003\t0\t0\t303\t00000000\n";
+
+        assert!(has_default_route(&format!(
+            "{}{}{}",
+            header, local, default
+        )));
+        assert!(!has_default_route(&format!("{}{}", header, local)));
+        assert!(!has_default_route(""));
+    }
+
+    #[test]
+    fn counts_disk_usage_like_df() {
+        // 10% of the blocks are reserved for root.
+        assert_eq!(usage_percent(1000, 200, 100), 800.0 / 900.0 * 100.0);
+        assert_eq!(usage_percent(0, 0, 0), 0.0);
+    }
+
+    #[test]
+    fn highest_priority_wins() {
+        let shown = |color| Indication {
+            name: "status".to_owned(),
+            color,
+            pattern: Pattern::Solid,
+        };
+        let red = Color::rgb(255, 0, 0);
+        let blue = Color::rgb(0, 0, 255);
+        let green = Color::rgb(0, 255, 0);
+
+        assert_eq!(highest(&[None, None]), None);
+        assert_eq!(
+            highest(&[Some((1, shown(red))), Some((5, shown(blue))), None]),
+            Some(shown(blue))
+        );
+        assert_eq!(
+            highest(&[None, Some((5, shown(green))), Some((5, shown(blue)))]),
+            Some(shown(green))
+        );
+    }
+}
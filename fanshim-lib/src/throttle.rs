@@ -0,0 +1,191 @@
+use std::fmt;
+use std::process::Command;
+use std::{fs, io};
+
+const SYSFS_GET_THROTTLED: &str = "/sys/devices/platform/soc/soc:firmware/get_throttled";
+/// Bits of the conditions that have occurred since boot are this much higher than the bits
+/// of the conditions that are active now.
+const OCCURRED_SHIFT: u32 = 16;
+
+/// A condition the Raspberry Pi firmware reports in its throttle flags.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Condition {
+    UnderVoltage,
+    FrequencyCapped,
+    Throttled,
+    SoftTemperatureLimit,
+}
+
+impl Condition {
+    pub const ALL: [Condition; 4] = [
+        Condition::UnderVoltage,
+        Condition::FrequencyCapped,
+        Condition::Throttled,
+        Condition::SoftTemperatureLimit,
+    ];
+
+    fn bit(self) -> u32 {
+        match self {
+            Condition::UnderVoltage => 1 << 0,
+            Condition::FrequencyCapped => 1 << 1,
+            Condition::Throttled => 1 << 2,
+            Condition::SoftTemperatureLimit => 1 << 3,
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Condition::UnderVoltage => "under-voltage",
+            Condition::FrequencyCapped => "frequency capped",
+            Condition::Throttled => "throttled",
+            Condition::SoftTemperatureLimit => "soft temperature limit",
+        })
+    }
+}
+
+/// A change between two readings of the throttle flags.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Transition {
+    Started(Condition),
+    Ended(Condition),
+    /// The condition was recorded as having occurred since boot, possibly between two readings.
+    Occurred(Condition),
+}
+
+/// The throttle flags as reported by `vcgencmd get_throttled`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Throttled(pub u32);
+
+impl Throttled {
+    pub fn is_active(self, condition: Condition) -> bool {
+        self.0 & condition.bit() != 0
+    }
+
+    pub fn has_occurred(self, condition: Condition) -> bool {
+        self.0 & (condition.bit() << OCCURRED_SHIFT) != 0
+    }
+
+    /// Whether any condition is active now.
+    pub fn any_active(self) -> bool {
+        Condition::ALL.iter().any(|&c| self.is_active(c))
+    }
+
+    /// The changes since `previous`.
+    pub fn transitions(self, previous: Throttled) -> Vec<Transition> {
+        let mut transitions = Vec::new();
+        for &c in Condition::ALL.iter() {
+            match (previous.is_active(c), self.is_active(c)) {
+                (false, true) => transitions.push(Transition::Started(c)),
+                (true, false) => transitions.push(Transition::Ended(c)),
+                _ => (),
+            }
+            if !previous.has_occurred(c) && self.has_occurred(c) && !self.is_active(c) {
+                transitions.push(Transition::Occurred(c));
+            }
+        }
+        transitions
+    }
+}
+
+/// The active conditions, separated by commas, or "none".
+impl fmt::Display for Throttled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let active: Vec<String> = Condition::ALL
+            .iter()
+            .filter(|&&c| self.is_active(c))
+            .map(ToString::to_string)
+            .collect();
+        if active.is_empty() {
+            f.write_str("none")
+        } else {
+            f.write_str(&active.join(", "))
+        }
+    }
+}
+
+/// Parses the output of `vcgencmd get_throttled`, like `throttled=0x50005`, or the plain hex
+/// value of the sysfs `get_throttled` node.
+pub fn parse(output: &str) -> Option<Throttled> {
+    let value = output.trim();
+    let value = value.strip_prefix("throttled=").unwrap_or(value);
+    let value = value.strip_prefix("0x").unwrap_or(value);
+    u32::from_str_radix(value, 16).ok().map(Throttled)
+}
+
+/// Reads the throttle flags from sysfs, or from `vcgencmd` on kernels without the node.
+pub fn read() -> io::Result<Throttled> {
+    let output = match fs::read_to_string(SYSFS_GET_THROTTLED) {
+        Ok(output) => output,
+        Err(_) => {
+            let output = Command::new("vcgencmd").arg("get_throttled").output()?;
+            if !output.status.success() {
+                return Err(io::Error::other("vcgencmd get_throttled failed"));
+            }
+            String::from_utf8_lossy(&output.stdout).into_owned()
+        }
+    };
+    parse(&output).ok_or_else(|| io::Error::other("Could not parse throttle flags"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse, Condition, Throttled, Transition};
+
+    #[test]
+    fn parses_vcgencmd_and_sysfs_output() {
+        assert_eq!(parse("throttled=0x50005\n"), Some(Throttled(0x50005)));
+        assert_eq!(parse("throttled=0x0"), Some(Throttled(0)));
+        assert_eq!(parse("50000\n"), Some(Throttled(0x50000)));
+        assert_eq!(parse("error=1"), None);
+    }
+
+    #[test]
+    fn decodes_known_bitmasks() {
+        // Under-voltage and throttled now, both also recorded since boot.
+        let t = Throttled(0x50005);
+        assert!(t.is_active(Condition::UnderVoltage));
+        assert!(!t.is_active(Condition::FrequencyCapped));
+        assert!(t.is_active(Condition::Throttled));
+        assert!(t.has_occurred(Condition::UnderVoltage));
+        assert!(t.has_occurred(Condition::Throttled));
+        assert!(!t.has_occurred(Condition::SoftTemperatureLimit));
+        assert_eq!(t.to_string(), "under-voltage, throttled");
+
+        // Everything happened since boot, but nothing is active now.
+        let t = Throttled(0xf0000);
+        assert!(!t.any_active());
+        assert!(Condition::ALL.iter().all(|&c| t.has_occurred(c)));
+        assert_eq!(t.to_string(), "none");
+
+        assert_eq!(Throttled(0x8).to_string(), "soft temperature limit");
+        assert_eq!(Throttled(0x2).to_string(), "frequency capped");
+    }
+
+    #[test]
+    fn reports_transitions() {
+        assert_eq!(
+            Throttled(0x50005).transitions(Throttled(0)),
+            vec![
+                Transition::Started(Condition::UnderVoltage),
+                Transition::Started(Condition::Throttled)
+            ]
+        );
+        assert_eq!(
+            Throttled(0x50000).transitions(Throttled(0x50005)),
+            vec![
+                Transition::Ended(Condition::UnderVoltage),
+                Transition::Ended(Condition::Throttled)
+            ]
+        );
+        // Frequency capping came and went between two readings.
+        assert_eq!(
+            Throttled(0x20000).transitions(Throttled(0)),
+            vec![Transition::Occurred(Condition::FrequencyCapped)]
+        );
+        assert!(Throttled(0x50005)
+            .transitions(Throttled(0x50005))
+            .is_empty());
+    }
+}
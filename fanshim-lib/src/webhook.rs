@@ -0,0 +1,290 @@
+use crate::config;
+use crate::hooks::{Event, Trigger};
+use crate::MILLI_CELSIUS_IN_CELSIUS;
+use log::{debug, warn};
+use std::collections::VecDeque;
+use std::fs;
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const HOSTNAME_FILE: &str = "/proc/sys/kernel/hostname";
+const HOUR: Duration = Duration::from_secs(60 * 60);
+
+/// The JSON body of a notification.
+#[derive(serde::Serialize, Debug, Clone, PartialEq)]
+pub struct Payload {
+    pub hostname: String,
+    /// Degrees celsius.
+    pub temperature: f64,
+    /// "fan_on" or "fan_off".
+    pub state: String,
+    /// E.g. "alarm".
+    pub event: String,
+    /// What is wrong, e.g. "network down", empty unless an alarm is active.
+    pub alarms: Vec<String>,
+}
+
+impl Payload {
+    pub fn new(hostname: &str, trigger: &Trigger) -> Self {
+        Self {
+            hostname: hostname.to_owned(),
+            temperature: f64::from(trigger.temperature.0) / f64::from(MILLI_CELSIUS_IN_CELSIUS),
+            state: if trigger.fan_enabled {
+                Event::FanOn.state().to_owned()
+            } else {
+                Event::FanOff.state().to_owned()
+            },
+            event: trigger.event.state().to_owned(),
+            alarms: trigger.alarms.clone(),
+        }
+    }
+}
+
+/// Allows at most `max_per_hour` notifications within any hour, all of them if 0.
+#[derive(Debug)]
+struct RateLimit {
+    max_per_hour: u32,
+    sent: VecDeque<Instant>,
+}
+
+impl RateLimit {
+    fn new(max_per_hour: u32) -> Self {
+        Self {
+            max_per_hour,
+            sent: VecDeque::new(),
+        }
+    }
+
+    /// Whether a notification may be sent at `now`, counting it if so.
+    fn allows(&mut self, now: Instant) -> bool {
+        if self.max_per_hour == 0 {
+            return true;
+        }
+        while self
+            .sent
+            .front()
+            .is_some_and(|&t| now.saturating_duration_since(t) >= HOUR)
+        {
+            self.sent.pop_front();
+        }
+        if self.sent.len() < self.max_per_hour as usize {
+            self.sent.push_back(now);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Sends the notifications of the `[webhook]` section from a thread of its own, so retries do
+/// not hold up the driver loop. The thread ends once this is dropped.
+pub struct Webhook {
+    config: config::Webhook,
+    hostname: String,
+    limit: RateLimit,
+    sender: Option<Sender<Payload>>,
+}
+
+impl Webhook {
+    pub fn new(config: &config::Webhook) -> Self {
+        let sender = config.url.clone().map(|url| {
+            let (sender, receiver) = mpsc::channel::<Payload>();
+            let config = config.clone();
+            thread::spawn(move || {
+                for payload in receiver {
+                    match deliver(&config, &url, &payload) {
+                        Ok(()) => debug!("Sent webhook notification of {}", payload.event),
+                        Err(e) => warn!(
+                            "Unable to send webhook notification of {}: {}",
+                            payload.event, e
+                        ),
+                    }
+                }
+            });
+            sender
+        });
+        Self {
+            config: config.clone(),
+            hostname: hostname(),
+            limit: RateLimit::new(config.max_per_hour),
+            sender,
+        }
+    }
+
+    pub fn config(&self) -> &config::Webhook {
+        &self.config
+    }
+
+    /// Queues a notification of `trigger`, if its event is configured and the rate limit allows.
+    pub fn notify(&mut self, trigger: &Trigger) {
+        let sender = match &self.sender {
+            Some(sender) if self.config.events.contains(&trigger.event) => sender,
+            _ => return,
+        };
+        if !self.limit.allows(Instant::now()) {
+            warn!(
+                "Not sending webhook notification of {}, {} were sent within the last hour",
+                trigger.event.state(),
+                self.config.max_per_hour
+            );
+            return;
+        }
+        let _ = sender.send(Payload::new(&self.hostname, trigger));
+    }
+}
+
+fn hostname() -> String {
+    fs::read_to_string(HOSTNAME_FILE)
+        .map(|h| h.trim().to_owned())
+        .unwrap_or_else(|_| "unknown".to_owned())
+}
+
+/// Posts `payload` to `url`, retrying with a doubling delay after server and transport errors.
+pub fn deliver(config: &config::Webhook, url: &str, payload: &Payload) -> Result<(), String> {
+    let agent = ureq::AgentBuilder::new()
+        .timeout(Duration::from_secs(config.timeout_seconds))
+        .build();
+    let body = serde_json::to_string(payload).map_err(|e| e.to_string())?;
+    let mut delay = Duration::from_secs(config.retry_delay_seconds);
+    let mut attempt = 0;
+    loop {
+        let error = match agent
+            .post(url)
+            .set("Content-Type", "application/json")
+            .send_string(&body)
+        {
+            Ok(_) => return Ok(()),
+            Err(ureq::Error::Status(code, _)) if code != 429 && code < 500 => {
+                return Err(format!("the server answered {}", code));
+            }
+            Err(ureq::Error::Status(code, _)) => format!("the server answered {}", code),
+            Err(ureq::Error::Transport(e)) => e.to_string(),
+        };
+        if attempt >= config.retries {
+            return Err(error);
+        }
+        attempt += 1;
+        debug!(
+            "Webhook notification of {} failed, retrying in {:?}: {}",
+            payload.event, delay, error
+        );
+        thread::sleep(delay);
+        delay *= 2;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{deliver, Payload, RateLimit, HOUR};
+    use crate::config;
+    use crate::hooks::{Event, Trigger};
+    use crate::MilliCelsius;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    /// Answers each request with the next of `statuses`, returning the request bodies.
+    fn serve(statuses: &'static [u16]) -> (String, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/notify", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let mut bodies = Vec::new();
+            for status in statuses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let line = line.trim().to_ascii_lowercase();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some(value) = line.strip_prefix("content-length:") {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                bodies.push(String::from_utf8(body).unwrap());
+                write!(
+                    reader.get_mut(),
+                    "HTTP/1.1 {} Status\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    status
+                )
+                .unwrap();
+            }
+            bodies
+        });
+        (url, server)
+    }
+
+    fn payload() -> Payload {
+        Payload::new(
+            "pi",
+            &Trigger {
+                event: Event::Alarm,
+                temperature: MilliCelsius(71_500),
+                fan_enabled: true,
+                profile: None,
+                alarms: vec!["network down".to_owned(), "under-voltage".to_owned()],
+            },
+        )
+    }
+
+    #[test]
+    fn retries_until_delivered() {
+        let (url, server) = serve(&[500, 429, 200]);
+        let config = config::Webhook {
+            retry_delay_seconds: 0,
+            ..config::Webhook::default()
+        };
+
+        assert_eq!(deliver(&config, &url, &payload()), Ok(()));
+
+        let bodies = server.join().unwrap();
+        assert_eq!(bodies.len(), 3);
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&bodies[2]).unwrap(),
+            serde_json::json!({
+                "hostname": "pi",
+                "temperature": 71.5,
+                "state": "fan_on",
+                "event": "alarm",
+                "alarms": ["network down", "under-voltage"],
+            })
+        );
+    }
+
+    #[test]
+    fn gives_up_on_client_errors_and_after_retries() {
+        let config = config::Webhook {
+            retries: 1,
+            retry_delay_seconds: 0,
+            ..config::Webhook::default()
+        };
+
+        let (url, server) = serve(&[404]);
+        assert!(deliver(&config, &url, &payload()).is_err());
+        assert_eq!(server.join().unwrap().len(), 1);
+
+        let (url, server) = serve(&[503, 503]);
+        assert!(deliver(&config, &url, &payload()).is_err());
+        assert_eq!(server.join().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn rate_limit_allows_max_per_hour() {
+        let start = Instant::now();
+        let mut limit = RateLimit::new(2);
+
+        assert!(limit.allows(start));
+        assert!(limit.allows(start + Duration::from_secs(60)));
+        assert!(!limit.allows(start + Duration::from_secs(120)));
+        assert!(limit.allows(start + HOUR));
+        assert!(!limit.allows(start + HOUR + Duration::from_secs(30)));
+        assert!(RateLimit::new(0).allows(start));
+    }
+}
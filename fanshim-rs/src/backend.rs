@@ -0,0 +1,63 @@
+use fanshim_lib::button::Button;
+use fanshim_lib::config::FanshimConfig;
+use fanshim_lib::sim::SimShim;
+use fanshim_lib::{RealShim, ShimLayer};
+use log::warn;
+use std::time::Duration;
+
+/// Which `ShimLayer` the driver runs on, chosen with `--backend`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    /// The Fan SHIM hardware.
+    Real,
+    /// The thermal model from the `[simulation]` section of the config file.
+    Sim,
+}
+
+/// What the driver needs from a shim on top of `ShimLayer`.
+pub trait BackendShim: ShimLayer {
+    /// Called with every config that is loaded, before the controller uses it.
+    fn apply_config(&mut self, config: &FanshimConfig);
+
+    /// How often the service manager expects a watchdog ping, if at all.
+    fn watchdog_interval(&self) -> Option<Duration>;
+
+    /// The button, if there is one.
+    fn button(&self) -> Option<Button>;
+}
+
+impl BackendShim for RealShim {
+    fn apply_config(&mut self, config: &FanshimConfig) {
+        fanshim_lib::exit::install_panic_hook(config.exit_state, config.led_pixels);
+        self.set_exit_state(config.exit_state);
+        self.set_led_pixels(config.led_pixels);
+        self.set_led_output(config.led_output);
+        self.set_status_rules(&config.status);
+        self.set_hooks(&config.hooks);
+        self.set_webhook(&config.webhook);
+    }
+
+    fn watchdog_interval(&self) -> Option<Duration> {
+        RealShim::watchdog_interval(self)
+    }
+
+    fn button(&self) -> Option<Button> {
+        RealShim::button(self)
+            .map_err(|e| warn!("Unable to use the button: {}", e))
+            .ok()
+    }
+}
+
+impl BackendShim for SimShim {
+    fn apply_config(&mut self, config: &FanshimConfig) {
+        self.set_cpu_load(config.simulation.cpu_load);
+    }
+
+    fn watchdog_interval(&self) -> Option<Duration> {
+        None
+    }
+
+    fn button(&self) -> Option<Button> {
+        None
+    }
+}
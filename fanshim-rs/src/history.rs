@@ -0,0 +1,37 @@
+use fanshim_lib::config::FanshimConfig;
+use fanshim_lib::history;
+use std::io::{self, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Copy)]
+pub enum Format {
+    Csv,
+    Json,
+}
+
+/// Prints the history file kept by the running driver to stdout.
+pub fn print(config: &FanshimConfig, format: Format, since: Option<Duration>) -> io::Result<()> {
+    let path = config.history.file.as_ref().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            "no history file is set in the [history] section of the config file",
+        )
+    })?;
+
+    let oldest = since.map_or(0, |since| {
+        SystemTime::now()
+            .checked_sub(since)
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_secs())
+    });
+    let entries = history::read_file(path)?;
+    let entries = entries.iter().filter(|e| e.timestamp >= oldest);
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    match format {
+        Format::Csv => history::write_csv(&mut out, entries)?,
+        Format::Json => history::write_json(&mut out, entries)?,
+    }
+    out.flush()
+}
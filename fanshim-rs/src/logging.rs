@@ -0,0 +1,444 @@
+use fanshim_lib::config::{LogTarget, Logging};
+use log::kv::{self, Key, Value, VisitSource};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use simplelog::{Config, TermLogger, TerminalMode, WriteLogger};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+const SYSLOG_SOCKET: &str = "/dev/log";
+const SYSLOG_FACILITY_DAEMON: u8 = 3;
+const IDENTIFIER: &str = env!("CARGO_PKG_NAME");
+/// Prefix of the journald fields made from a log record's key-values, e.g. `FANSHIM_TEMP`.
+const JOURNALD_FIELD_PREFIX: &str = "FANSHIM_";
+
+/// The global logger. Starts out logging to the terminal and is reconfigured once the config file is read.
+pub struct Logger {
+    inner: RwLock<Inner>,
+}
+
+struct Inner {
+    filter: Filter,
+    output: Box<dyn Log>,
+}
+
+impl Logger {
+    /// Installs the logger, logging everything at `level` and above to the terminal.
+    pub fn init(level: LevelFilter) -> fanshim_lib::Result<&'static Logger> {
+        let logger: &'static Logger = Box::leak(Box::new(Logger {
+            inner: RwLock::new(Inner {
+                filter: Filter::new(level, Vec::new()),
+                output: terminal(),
+            }),
+        }));
+        log::set_logger(logger)?;
+        log::set_max_level(level);
+        Ok(logger)
+    }
+
+    /// Switches to the settings from `logging`. `level_override` replaces the configured level,
+    /// e.g. when `--verbose` is passed.
+    pub fn configure(&self, logging: &Logging, level_override: Option<LevelFilter>) {
+        let level = level_override.unwrap_or(logging.level);
+        let filter = Filter::new(
+            level,
+            logging
+                .modules
+                .iter()
+                .map(|(module, level)| (module.clone(), *level))
+                .collect(),
+        );
+
+        let output = match output(logging) {
+            Ok(o) => o,
+            Err(e) => {
+                eprintln!(
+                    "Unable to log to {:?}: {}. Logging to the terminal instead.",
+                    logging.target, e
+                );
+                terminal()
+            }
+        };
+
+        log::set_max_level(filter.max_level());
+        let mut inner = self.inner.write().unwrap_or_else(|e| e.into_inner());
+        inner.output.flush();
+        *inner = Inner { filter, output };
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        match self.inner.read() {
+            Ok(inner) => inner.filter.enabled(metadata),
+            Err(_) => false,
+        }
+    }
+
+    fn log(&self, record: &Record) {
+        if let Ok(inner) = self.inner.read() {
+            if inner.filter.enabled(record.metadata()) {
+                inner.output.log(record);
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(inner) = self.inner.read() {
+            inner.output.flush();
+        }
+    }
+}
+
+fn terminal() -> Box<dyn Log> {
+    TermLogger::new(LevelFilter::Trace, Config::default(), TerminalMode::Stderr)
+}
+
+fn output(logging: &Logging) -> io::Result<Box<dyn Log>> {
+    Ok(match logging.target {
+        LogTarget::Stderr => terminal(),
+        LogTarget::Journald => Box::new(Journald::connect(Path::new(JOURNALD_SOCKET))?),
+        LogTarget::Syslog => Box::new(Syslog::connect(Path::new(SYSLOG_SOCKET))?),
+        LogTarget::File => WriteLogger::new(
+            LevelFilter::Trace,
+            Config::default(),
+            RotatingFile::open(
+                &logging.file,
+                logging.max_file_size_kilobytes * 1024,
+                logging.max_rotated_files,
+            )?,
+        ),
+    })
+}
+
+/// A default level plus per-module levels, where the longest matching module path wins.
+struct Filter {
+    default: LevelFilter,
+    modules: Vec<(String, LevelFilter)>,
+}
+
+impl Filter {
+    fn new(default: LevelFilter, mut modules: Vec<(String, LevelFilter)>) -> Self {
+        modules.sort_by_key(|(module, _)| std::cmp::Reverse(module.len()));
+        Self { default, modules }
+    }
+
+    fn level_for(&self, target: &str) -> LevelFilter {
+        self.modules
+            .iter()
+            .find(|(module, _)| {
+                target == module
+                    || (target.starts_with(module.as_str())
+                        && target[module.len()..].starts_with("::"))
+            })
+            .map_or(self.default, |(_, level)| *level)
+    }
+
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level_for(metadata.target())
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        self.modules
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default, Ord::max)
+    }
+}
+
+fn syslog_severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+/// Writes to the systemd journal using its native protocol, see `man systemd.journal-fields`.
+struct Journald {
+    socket: UnixDatagram,
+    path: PathBuf,
+}
+
+impl Journald {
+    fn connect(path: &Path) -> io::Result<Self> {
+        if !path.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} does not exist", path.display()),
+            ));
+        }
+        Ok(Self {
+            socket: UnixDatagram::unbound()?,
+            path: path.to_owned(),
+        })
+    }
+}
+
+impl Log for Journald {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        let message = journald_message(record);
+        if let Err(e) = self.socket.send_to(&message, &self.path) {
+            eprintln!("Unable to write to journald: {}", e);
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+fn journald_message(record: &Record) -> Vec<u8> {
+    let mut buf = Vec::new();
+    append_journald_field(&mut buf, "MESSAGE", &record.args().to_string());
+    append_journald_field(
+        &mut buf,
+        "PRIORITY",
+        &syslog_severity(record.level()).to_string(),
+    );
+    append_journald_field(&mut buf, "SYSLOG_IDENTIFIER", IDENTIFIER);
+    append_journald_field(&mut buf, "TARGET", record.target());
+    if let Some(file) = record.file() {
+        append_journald_field(&mut buf, "CODE_FILE", file);
+    }
+    if let Some(line) = record.line() {
+        append_journald_field(&mut buf, "CODE_LINE", &line.to_string());
+    }
+
+    struct Fields<'a>(&'a mut Vec<u8>);
+    impl<'kvs> VisitSource<'kvs> for Fields<'_> {
+        fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+            let name: String = key
+                .as_str()
+                .chars()
+                .map(|c| match c {
+                    'a'..='z' | 'A'..='Z' | '0'..='9' => c.to_ascii_uppercase(),
+                    _ => '_',
+                })
+                .collect();
+            append_journald_field(
+                self.0,
+                &format!("{}{}", JOURNALD_FIELD_PREFIX, name),
+                &value.to_string(),
+            );
+            Ok(())
+        }
+    }
+    let _ = record.key_values().visit(&mut Fields(&mut buf));
+
+    buf
+}
+
+/// Appends `name=value\n`, or the length-prefixed binary form if `value` contains a newline.
+fn append_journald_field(buf: &mut Vec<u8>, name: &str, value: &str) {
+    buf.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+        buf.push(b'\n');
+        buf.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        buf.push(b'=');
+    }
+    buf.extend_from_slice(value.as_bytes());
+    buf.push(b'\n');
+}
+
+/// Writes RFC 3164 messages to the local syslog socket.
+struct Syslog {
+    socket: UnixDatagram,
+}
+
+impl Syslog {
+    fn connect(path: &Path) -> io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(path)?;
+        Ok(Self { socket })
+    }
+}
+
+impl Log for Syslog {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        let message = format!(
+            "<{}>{}[{}]: {}",
+            SYSLOG_FACILITY_DAEMON * 8 + syslog_severity(record.level()),
+            IDENTIFIER,
+            std::process::id(),
+            record.args()
+        );
+        if let Err(e) = self.socket.send(message.as_bytes()) {
+            eprintln!("Unable to write to syslog: {}", e);
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// A log file that is renamed to `<file>.1` once it grows past `max_bytes`, shifting older
+/// files up to `<file>.<max_rotated_files>`.
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    written: u64,
+    max_bytes: u64,
+    max_rotated_files: u32,
+    /// Whether the last write ended a line. A record can come in several writes, and is only
+    /// rotated away from once complete.
+    at_line_start: bool,
+}
+
+impl RotatingFile {
+    fn open(path: &Path, max_bytes: u64, max_rotated_files: u32) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let written = file.metadata()?.len();
+        Ok(Self {
+            path: path.to_owned(),
+            file,
+            written,
+            max_bytes,
+            max_rotated_files,
+            at_line_start: true,
+        })
+    }
+
+    fn rotated_path(&self, n: u32) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.max_rotated_files == 0 {
+            self.file.set_len(0)?;
+        } else {
+            for n in (1..self.max_rotated_files).rev() {
+                let from = self.rotated_path(n);
+                if from.exists() {
+                    fs::rename(&from, self.rotated_path(n + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated_path(1))?;
+            self.file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+        }
+        self.written = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.at_line_start
+            && self.written > 0
+            && self.written + buf.len() as u64 > self.max_bytes
+        {
+            self.rotate()?;
+        }
+        let n = self.file.write(buf)?;
+        self.written += n as u64;
+        if n > 0 {
+            self.at_line_start = buf[n - 1] == b'\n';
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{journald_message, Filter, RotatingFile};
+    use log::{Level, LevelFilter, Record};
+    use std::io::Write;
+
+    #[test]
+    fn longest_module_filter_wins() {
+        let f = Filter::new(
+            LevelFilter::Info,
+            vec![
+                ("fanshim_lib".to_owned(), LevelFilter::Warn),
+                ("fanshim_lib::notify".to_owned(), LevelFilter::Trace),
+            ],
+        );
+
+        assert_eq!(f.level_for("fanshim_rs"), LevelFilter::Info);
+        assert_eq!(f.level_for("fanshim_lib"), LevelFilter::Warn);
+        assert_eq!(f.level_for("fanshim_lib::fan"), LevelFilter::Warn);
+        assert_eq!(f.level_for("fanshim_lib::notify"), LevelFilter::Trace);
+        assert_eq!(f.level_for("fanshim_library"), LevelFilter::Info);
+        assert_eq!(f.max_level(), LevelFilter::Trace);
+    }
+
+    #[test]
+    fn journald_message_includes_structured_fields() {
+        let kvs = [("temp", 55_000), ("fan_duty", 100)];
+        let message = journald_message(
+            &Record::builder()
+                .args(format_args!("two\nlines"))
+                .level(Level::Warn)
+                .target("fanshim_lib")
+                .key_values(&kvs)
+                .build(),
+        );
+
+        let mut expected = b"MESSAGE\n".to_vec();
+        expected.extend_from_slice(&9u64.to_le_bytes());
+        expected.extend_from_slice(b"two\nlines\n");
+        expected.extend_from_slice(b"PRIORITY=4\n");
+        expected.extend_from_slice(b"SYSLOG_IDENTIFIER=fanshim-rs\n");
+        expected.extend_from_slice(b"TARGET=fanshim_lib\n");
+        expected.extend_from_slice(b"FANSHIM_TEMP=55000\n");
+        expected.extend_from_slice(b"FANSHIM_FAN_DUTY=100\n");
+        assert_eq!(message, expected);
+    }
+
+    #[test]
+    fn file_rotates_when_full() {
+        let dir = std::env::temp_dir().join(format!("fanshim-log-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("fanshim-rs.log");
+
+        let mut f = RotatingFile::open(&path, 10, 2).unwrap();
+        for line in &["first\n", "second\n", "third\n", "fourth\n"] {
+            f.write_all(line.as_bytes()).unwrap();
+        }
+
+        let read = |name: &str| std::fs::read_to_string(dir.join(name)).unwrap_or_default();
+        assert_eq!(read("fanshim-rs.log"), "fourth\n");
+        assert_eq!(read("fanshim-rs.log.1"), "third\n");
+        assert_eq!(read("fanshim-rs.log.2"), "second\n");
+        assert!(!dir.join("fanshim-rs.log.3").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn file_rotates_between_lines_only() {
+        let dir = std::env::temp_dir().join(format!("fanshim-log-lines-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("fanshim-rs.log");
+
+        let mut f = RotatingFile::open(&path, 10, 1).unwrap();
+        for part in &["first\n", "sec", "ond", " line\n", "third\n"] {
+            f.write_all(part.as_bytes()).unwrap();
+        }
+
+        let read = |name: &str| std::fs::read_to_string(dir.join(name)).unwrap_or_default();
+        assert_eq!(read("fanshim-rs.log"), "third\n");
+        assert_eq!(read("fanshim-rs.log.1"), "first\nsecond line\n");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
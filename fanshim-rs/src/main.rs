@@ -1,13 +1,30 @@
+use backend::{Backend, BackendShim};
+use fanshim_lib::cancel::CancellationToken;
 use fanshim_lib::config::FanshimConfig;
-use log::{error, info};
+use fanshim_lib::hooks::{Event, Trigger};
+use fanshim_lib::notify::NotifyState;
+use fanshim_lib::Controller;
+use log::{error, info, warn, LevelFilter};
+use logging::Logger;
 use simple_signal::Signal;
-use simplelog::{CombinedLogger, Config, LevelFilter, TermLogger, TerminalMode};
 use std::env;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::exit;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+mod backend;
+mod history;
+mod logging;
+mod profile;
+mod replay;
 
 const SUCCESSFUL_EXIT: i32 = 0;
 const UNKNOWN_ARGUMENT: i32 = 1;
+const HISTORY_UNAVAILABLE: i32 = 2;
+const REPLAY_FAILED: i32 = 3;
+const PROFILE_UNAVAILABLE: i32 = 4;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 const AUTHOR: &str = env!("CARGO_PKG_AUTHORS");
@@ -15,49 +32,202 @@ const PACKAGE_NAME: &str = env!("CARGO_PKG_NAME");
 const CONFIG_FILE_LOCATION: &str = "/etc/fanshim-rs.toml";
 
 fn main() -> fanshim_lib::Result<()> {
-    CombinedLogger::init(vec![TermLogger::new(
-        LevelFilter::Info,
-        Config::default(),
-        TerminalMode::Mixed,
-    )])?;
-
-    handle_arguments();
-
-    let config = {
-        match fanshim_lib::config::read_config_file(Path::new(CONFIG_FILE_LOCATION)) {
-            Ok(c) => c,
-            Err(_) => {
-                error!("Error parsing config file. Using defaults.");
-                FanshimConfig::default()
+    let args = handle_arguments();
+    let logger = Logger::init(args.level_override.unwrap_or(LevelFilter::Info))?;
+
+    let config = load_config(&args.config_file);
+
+    match &args.command {
+        Command::Run => (),
+        Command::History { format, since } => {
+            if let Err(e) = history::print(&config, *format, *since) {
+                error!("Unable to show history: {}", e);
+                exit(HISTORY_UNAVAILABLE);
             }
+            return Ok(());
         }
-    };
-    info!("Loaded with config: {:#?}", config);
+        Command::Replay { trace } => {
+            if let Err(e) = replay::run(&config, trace) {
+                error!("Unable to replay {}: {}", trace.display(), e);
+                exit(REPLAY_FAILED);
+            }
+            return Ok(());
+        }
+        Command::Profile { name } => {
+            if let Err(e) = profile::request(&config, name) {
+                error!("Unable to switch to profile {}: {}", name, e);
+                exit(PROFILE_UNAVAILABLE);
+            }
+            return Ok(());
+        }
+    }
 
-    let mut s = fanshim_lib::RealShim::new();
+    let config = profile::apply_requested(config);
+    logger.configure(&config.logging, args.level_override);
+    info!("Loaded with config: {:#?}", config);
+    log_profile(&config);
 
-    simple_signal::set_handler(&[Signal::Int, Signal::Term], |signals| {
-        fanshim_lib::led::set_led_rgb_brightness(0, 0, 0, 0.0);
-        fanshim_lib::fan::turn_fan_off();
-        if signals.contains(&Signal::Int) {
-            info!("Received interrupt signal. Exiting.");
-        } else if signals.contains(&Signal::Term) {
-            info!("Received termination signal. Exiting.");
+    match args.backend {
+        Backend::Real => run(fanshim_lib::RealShim::new(), config, logger, &args),
+        Backend::Sim => {
+            let s = fanshim_lib::sim::SimShim::new(config.simulation.clone());
+            run(s, config, logger, &args)
         }
-        exit(SUCCESSFUL_EXIT);
-    });
+    }
+}
 
+fn run<S>(
+    mut s: S,
+    mut config: FanshimConfig,
+    logger: &Logger,
+    args: &Arguments,
+) -> fanshim_lib::Result<()>
+where
+    S: BackendShim,
+{
+    let token = CancellationToken::new();
+    let switcher = profile::Switcher::start(token.clone(), s.button());
+    switcher.set_profile_file(config.profile_file.clone());
+    let stop_requested = Arc::new(AtomicBool::new(false));
+    let reload_requested = Arc::new(AtomicBool::new(false));
+    {
+        let stop_requested = Arc::clone(&stop_requested);
+        let reload_requested = Arc::clone(&reload_requested);
+        let token = token.clone();
+        simple_signal::set_handler(&[Signal::Int, Signal::Term, Signal::Hup], move |signals| {
+            if signals.contains(&Signal::Int) {
+                info!("Received interrupt signal. Exiting.");
+                stop_requested.store(true, Ordering::SeqCst);
+            } else if signals.contains(&Signal::Term) {
+                info!("Received termination signal. Exiting.");
+                stop_requested.store(true, Ordering::SeqCst);
+            } else if signals.contains(&Signal::Hup) {
+                info!("Received hangup signal. Reloading config file.");
+                reload_requested.store(true, Ordering::SeqCst);
+            }
+            token.cancel();
+        });
+    }
+
+    fanshim_lib::notify::fit_watchdog(&mut config, s.watchdog_interval());
+    // The profile file is looked at no more often than the temperature is read.
+    switcher.set_poll_interval(config.delay_between_readings);
+    s.apply_config(&config);
     fanshim_lib::initial_setup(&config, &mut s);
-    fanshim_lib::main_loop(&config, &mut s);
-    Ok(())
+    s.notify(NotifyState::Ready);
+    // Kept across reloads and profile switches, so the fan carries on as it is.
+    let mut controller = Controller::new(&config);
+    loop {
+        controller.run_until_cancelled(&mut s, &token);
+        // Reset before looking at the flags, so a signal arriving in between cancels the next loop.
+        token.reset();
+
+        if switcher.take_button_long_press() {
+            let trigger = Trigger {
+                event: Event::ButtonLongPress,
+                temperature: s.get_cpu_temperature(),
+                fan_enabled: s.fan_is_enabled(),
+                profile: config.profile.clone(),
+                alarms: Vec::new(),
+            };
+            s.run_hook(trigger);
+        }
+
+        if stop_requested.load(Ordering::SeqCst) {
+            s.notify(NotifyState::Stopping);
+            // Dropping the shim applies the exit state.
+            return Ok(());
+        }
+
+        let reload = reload_requested.swap(false, Ordering::SeqCst);
+        if reload {
+            s.notify(NotifyState::Reloading);
+            config = profile::apply_requested(load_config(&args.config_file));
+            logger.configure(&config.logging, args.level_override);
+            info!("Loaded with config: {:#?}", config);
+            log_profile(&config);
+            switcher.set_profile_file(config.profile_file.clone());
+        }
+
+        let requested = match switcher.take_request() {
+            Some(profile::Request::Next) => {
+                let next = config.next_profile().map(str::to_owned);
+                if next.is_none() {
+                    warn!("There are no profiles in the config file to switch to.");
+                }
+                next
+            }
+            Some(profile::Request::FromFile) => profile::requested(&config),
+            None => None,
+        };
+        let switch = requested.is_some();
+        if let Some(name) = requested {
+            config = profile::switch(config, &name);
+            log_profile(&config);
+        }
+
+        if reload || switch {
+            fanshim_lib::notify::fit_watchdog(&mut config, s.watchdog_interval());
+            switcher.set_poll_interval(config.delay_between_readings);
+            s.apply_config(&config);
+            controller.reload(&config);
+        }
+        if reload {
+            s.notify(NotifyState::Ready);
+        }
+    }
+}
+
+fn log_profile(config: &FanshimConfig) {
+    if let Some(name) = &config.profile {
+        info!(profile = name.as_str(); "Using profile {}", name);
+    }
+}
+
+fn load_config(path: &Path) -> FanshimConfig {
+    match fanshim_lib::config::read_config_file(path) {
+        Ok(c) => c,
+        Err(e) => {
+            error!("Error parsing config file: {}. Using defaults.", e);
+            FanshimConfig::default()
+        }
+    }
 }
 
-fn handle_arguments() {
+struct Arguments {
+    command: Command,
+    backend: Backend,
+    config_file: PathBuf,
+    /// Log level set by `--verbose` or `--quiet`, replacing the one from the config file.
+    level_override: Option<LevelFilter>,
+}
+
+enum Command {
+    /// Run the driver.
+    Run,
+    /// Print the recorded temperature history.
+    History {
+        format: history::Format,
+        since: Option<Duration>,
+    },
+    /// Run a recorded temperature trace through the controller.
+    Replay { trace: PathBuf },
+    /// Ask the running driver to switch profiles.
+    Profile { name: String },
+}
+
+fn handle_arguments() -> Arguments {
     let args: Vec<String> = std::env::args().collect();
+    let mut arguments = Arguments {
+        command: Command::Run,
+        backend: Backend::Real,
+        config_file: PathBuf::from(CONFIG_FILE_LOCATION),
+        level_override: None,
+    };
 
     // Length of 1 means only one argument is passed; the name of the binary.
     if args.len() == 1 {
-        return;
+        return arguments;
     }
 
     let show_version = args.contains(&"--version".to_owned()) || args.contains(&"-v".to_owned());
@@ -84,18 +254,100 @@ fn handle_arguments() {
         println!();
         println!("USAGE:");
         println!("\t{} [FLAGS]", PACKAGE_NAME);
+        println!(
+            "\t{} history [--format csv|json] [--since DURATION]",
+            PACKAGE_NAME
+        );
+        println!("\t{} replay TRACE", PACKAGE_NAME);
+        println!("\t{} profile NAME", PACKAGE_NAME);
+        println!();
+        println!("COMMANDS:");
+        println!("\thistory\t\tPrints the recorded temperatures and fan actions");
+        println!("\t\t\t--format\tcsv (default) or json");
+        println!("\t\t\t--since\t\tOnly entries from e.g. the last 90s, 30m, 1h or 2d");
+        println!(
+            "\treplay\t\tRuns a CSV file of timestamps and temperatures, e.g. a history file,"
+        );
+        println!("\t\t\tthrough the controller and prints its decisions and a summary");
+        println!("\tprofile\t\tSwitches the running driver to the profile NAME");
         println!();
         println!("FLAGS:");
         println!("\t-h, --help\tPrints this message");
         println!("\t-v, --version\tPrints version information");
+        println!("\t--verbose\tLogs debug messages, overriding the config file");
+        println!("\t--quiet\t\tOnly logs warnings and errors, overriding the config file");
+        println!("\t--config FILE\tReads the config from FILE instead");
+        println!("\t--backend\treal (default) drives the Fan SHIM, sim runs the thermal model");
+        println!("\t\t\tfrom the [simulation] section of the config file instead");
         println!();
 
         exit(SUCCESSFUL_EXIT);
     }
 
-    println!("Unknown arguments passed to binary:");
+    let mut unknown_arguments = Vec::new();
     // We can slice the args because we checked for a length of 1 above.
-    for i in &args[1..] {
+    let mut rest = args[1..].iter();
+    while let Some(i) = rest.next() {
+        match (&mut arguments.command, i.as_str()) {
+            (_, "--verbose") => arguments.level_override = Some(LevelFilter::Debug),
+            (_, "--quiet") => arguments.level_override = Some(LevelFilter::Warn),
+            (Command::Run, "--backend") => match rest.next().map(String::as_str) {
+                Some("real") => arguments.backend = Backend::Real,
+                Some("sim") => arguments.backend = Backend::Sim,
+                _ => unknown_arguments.push(i),
+            },
+            (_, "--config") => match rest.next() {
+                Some(path) => arguments.config_file = PathBuf::from(path),
+                None => unknown_arguments.push(i),
+            },
+            (Command::Run, "replay") => match rest.next() {
+                Some(path) => {
+                    arguments.command = Command::Replay {
+                        trace: PathBuf::from(path),
+                    }
+                }
+                None => unknown_arguments.push(i),
+            },
+            (Command::Run, "profile") => match rest.next() {
+                Some(name) => {
+                    arguments.command = Command::Profile {
+                        name: name.to_owned(),
+                    }
+                }
+                None => unknown_arguments.push(i),
+            },
+            (Command::Run, "history") => {
+                arguments.command = Command::History {
+                    format: history::Format::Csv,
+                    since: None,
+                }
+            }
+            (Command::History { format, .. }, "--format") => {
+                match rest.next().map(String::as_str) {
+                    Some("csv") => *format = history::Format::Csv,
+                    Some("json") => *format = history::Format::Json,
+                    _ => unknown_arguments.push(i),
+                }
+            }
+            (Command::History { since, .. }, "--since") => {
+                match rest
+                    .next()
+                    .and_then(|d| fanshim_lib::history::parse_duration(d))
+                {
+                    Some(d) => *since = Some(d),
+                    None => unknown_arguments.push(i),
+                }
+            }
+            _ => unknown_arguments.push(i),
+        }
+    }
+
+    if unknown_arguments.is_empty() {
+        return arguments;
+    }
+
+    println!("Unknown arguments passed to binary:");
+    for i in unknown_arguments {
         println!("\t'{}'", i);
     }
     println!();
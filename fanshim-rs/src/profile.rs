@@ -0,0 +1,211 @@
+use fanshim_lib::button::{Button, LongPress};
+use fanshim_lib::cancel::CancellationToken;
+use fanshim_lib::config::FanshimConfig;
+use log::{info, warn};
+use signal_hook::consts::SIGUSR1;
+use signal_hook::iterator::Signals;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// How long the button has to be held to switch to the next profile.
+const LONG_PRESS: Duration = Duration::from_secs(2);
+
+/// A profile switch waiting to be applied by the driver loop.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Request {
+    /// Switch to the profile after the active one.
+    Next,
+    /// Switch to the profile written to the profile file by `fanshim-rs profile`.
+    FromFile,
+}
+
+struct Shared {
+    token: CancellationToken,
+    request: Option<Request>,
+    profile_file: Option<PathBuf>,
+    profile_file_changed: bool,
+    /// Time between two looks at the profile file.
+    poll_interval: Duration,
+    button_long_pressed: bool,
+}
+
+impl Shared {
+    fn request(&mut self, request: Request) {
+        self.request = Some(request);
+        self.token.cancel();
+    }
+}
+
+/// Watches for SIGUSR1, a long press of the button and changes to the profile file, and
+/// cancels the token so the driver loop can switch profiles.
+pub struct Switcher {
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl Switcher {
+    pub fn start(token: CancellationToken, button: Option<Button>) -> Self {
+        let shared = Arc::new(Mutex::new(Shared {
+            token,
+            request: None,
+            profile_file: None,
+            profile_file_changed: false,
+            poll_interval: Duration::from_secs(1),
+            button_long_pressed: false,
+        }));
+
+        match Signals::new([SIGUSR1]) {
+            Ok(mut signals) => {
+                let shared = Arc::clone(&shared);
+                thread::spawn(move || {
+                    for _ in signals.forever() {
+                        info!("Received user signal 1. Switching to the next profile.");
+                        shared.lock().unwrap().request(Request::Next);
+                    }
+                });
+            }
+            Err(e) => warn!("Unable to handle user signal 1: {}", e),
+        }
+
+        if let Some(button) = button {
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || watch_button(button, &shared));
+        }
+
+        let watched = Arc::clone(&shared);
+        thread::spawn(move || {
+            let mut last_modified = None;
+            loop {
+                let poll_interval = watched.lock().unwrap().poll_interval;
+                thread::sleep(poll_interval);
+
+                let mut shared = watched.lock().unwrap();
+                let modified = shared.profile_file.as_deref().and_then(modified);
+                if shared.profile_file_changed {
+                    shared.profile_file_changed = false;
+                } else if modified.is_some() && modified != last_modified {
+                    shared.request(Request::FromFile);
+                }
+                last_modified = modified;
+            }
+        });
+
+        Self { shared }
+    }
+
+    /// Sets the profile file to watch. The current contents of the file do not count as a request.
+    pub fn set_profile_file(&self, path: Option<PathBuf>) {
+        let mut shared = self.shared.lock().unwrap();
+        shared.profile_file = path;
+        shared.profile_file_changed = true;
+    }
+
+    /// Sets how often the profile file is looked at, from the next look on.
+    pub fn set_poll_interval(&self, poll_interval: Duration) {
+        self.shared.lock().unwrap().poll_interval = poll_interval;
+    }
+
+    pub fn take_request(&self) -> Option<Request> {
+        self.shared.lock().unwrap().request.take()
+    }
+
+    /// Whether the button was held since the last call.
+    pub fn take_button_long_press(&self) -> bool {
+        let mut shared = self.shared.lock().unwrap();
+        std::mem::take(&mut shared.button_long_pressed)
+    }
+}
+
+/// Requests the next profile whenever `button` is held. Sleeps until the button changes, or
+/// until a press would count.
+fn watch_button(mut button: Button, shared: &Mutex<Shared>) {
+    let mut long_press = LongPress::new(LONG_PRESS);
+    let mut pressed = button.is_pressed();
+    loop {
+        if long_press.update(pressed, Instant::now()) {
+            info!("Button held. Switching to the next profile.");
+            let mut shared = shared.lock().unwrap();
+            shared.button_long_pressed = true;
+            shared.request(Request::Next);
+        }
+
+        pressed = match button.wait_for_change(long_press.remaining(Instant::now())) {
+            Ok(pressed) => pressed,
+            Err(e) => {
+                warn!("Unable to watch the button: {}", e);
+                return;
+            }
+        };
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// The profile last written to the profile file, if any.
+pub fn requested(config: &FanshimConfig) -> Option<String> {
+    let path = config.profile_file.as_ref()?;
+    let name = fs::read_to_string(path).ok()?;
+    let name = name.trim();
+    if name.is_empty() {
+        None
+    } else {
+        Some(name.to_owned())
+    }
+}
+
+/// `config` with the profile from the profile file applied, if there is one.
+pub fn apply_requested(config: FanshimConfig) -> FanshimConfig {
+    match requested(&config) {
+        Some(name) => switch(config, &name),
+        None => config,
+    }
+}
+
+/// `config` with profile `name`, or `config` unchanged if there is no such profile.
+pub fn switch(config: FanshimConfig, name: &str) -> FanshimConfig {
+    match config.with_profile(Some(name)) {
+        Some(c) => c,
+        None => {
+            warn!(
+                "Profile '{}' does not exist. Keeping the current profile.",
+                name
+            );
+            config
+        }
+    }
+}
+
+/// Asks the running driver to switch to profile `name` by writing it to the profile file.
+pub fn request(config: &FanshimConfig, name: &str) -> io::Result<()> {
+    if !config.profiles.contains_key(name) {
+        let available: Vec<&str> = config.profiles.keys().map(String::as_str).collect();
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "there is no profile '{}', the config file has: {}",
+                name,
+                if available.is_empty() {
+                    "none".to_owned()
+                } else {
+                    available.join(", ")
+                }
+            ),
+        ));
+    }
+    let path = config.profile_file.as_ref().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            "no profile_file is set in the [general] section of the config file",
+        )
+    })?;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, format!("{}\n", name))
+}
@@ -0,0 +1,63 @@
+use fanshim_lib::config::FanshimConfig;
+use fanshim_lib::replay::{self, ReplayShim};
+use fanshim_lib::sim::Command;
+use fanshim_lib::MilliCelsius;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Runs the trace at `path` through the controller and prints its decisions and a summary.
+pub fn run(config: &FanshimConfig, path: &Path) -> io::Result<()> {
+    let mut config = config.clone();
+    // A replay must not mix its virtual readings into the history of the running driver.
+    config.history.file = None;
+
+    let mut s = ReplayShim::new(replay::read_trace(path)?);
+    fanshim_lib::initial_setup(&config, &mut s);
+    let token = s.cancellation_token();
+    fanshim_lib::main_loop(&config, &mut s, &token);
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    for c in s.commands() {
+        let command = match c.command {
+            Command::FanOn => "fan on".to_owned(),
+            Command::FanOff => "fan off".to_owned(),
+            Command::FanDuty(duty) => format!("fan at {:.0}%", duty * 100.0),
+            Command::Led {
+                r,
+                g,
+                b,
+                brightness,
+            } => format!("led [ {}, {}, {} ] at brightness {}", r, g, b, brightness),
+            Command::Hook(event) => format!("hook {}", event),
+        };
+        writeln!(out, "{:>8}s  {}", c.elapsed.as_secs(), command)?;
+    }
+
+    let summary = s.summary();
+    let duration = summary.duration.as_secs();
+    let fan_on = summary.fan_on_time.as_secs();
+    writeln!(out)?;
+    writeln!(out, "Duration:        {}s", duration)?;
+    writeln!(out, "Fan toggles:     {}", summary.toggles)?;
+    writeln!(
+        out,
+        "Time fan on:     {}s ({:.1}%)",
+        fan_on,
+        if duration == 0 {
+            0.0
+        } else {
+            fan_on as f64 * 100.0 / duration as f64
+        }
+    )?;
+    writeln!(
+        out,
+        "Max temperature: {:.1}°C",
+        celsius(summary.max_temperature)
+    )?;
+    out.flush()
+}
+
+fn celsius(temperature: MilliCelsius) -> f64 {
+    f64::from(temperature.0) / 1000.0
+}